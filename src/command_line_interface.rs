@@ -1,3 +1,5 @@
+use crate::log_entry;
+use chrono::prelude::*;
 use lazy_static::lazy_static;
 use std::path::PathBuf;
 use structopt::clap::AppSettings;
@@ -13,6 +15,253 @@ pub struct CLIOptions {
 	/// By default, ~/.config/timeplot/config.toml (or your XDG override)
 	#[structopt(short, long, name = "CONFIG_FILE", parse(from_os_str))]
 	pub config: Option<PathBuf>,
+
+	/// Run a one-off command instead of tracking activity
+	#[structopt(subcommand)]
+	pub command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+	/// Inspect or maintain the activity log
+	Log(LogCommand),
+	/// Import history from another activity tracker, assigning categories by the current rules
+	Import(ImportOptions),
+	/// Export the log for a time range, as raw entries or as merged sessions
+	Export(ExportOptions),
+	/// Record time that wasn't spent at the computer, like a meeting or a phone call.
+	/// Such entries are marked as manual and never changed automatically
+	Add {
+		/// Category to record, like the ones in your rules
+		#[structopt(long)]
+		category: String,
+		/// Start, like "14:00" (today) or "2018-10-01 14:00"
+		#[structopt(long, parse(try_from_str = parse_time_argument))]
+		from: DateTime<FixedOffset>,
+		/// End, like "15:30" (today) or "2018-10-01 15:30"
+		#[structopt(long, parse(try_from_str = parse_time_argument))]
+		to: DateTime<FixedOffset>,
+		/// Description, shown where window titles are
+		#[structopt(long)]
+		note: Option<String>,
+	},
+	/// Note an event like "started new medication" or "deploy day".
+	/// It's drawn on the graph as a labeled vertical line
+	Mark {
+		text: String,
+		/// When it happened, like "14:00" (today) or "2018-10-01 14:00". By default, now
+		#[structopt(long, parse(try_from_str = parse_time_argument))]
+		at: Option<DateTime<FixedOffset>>,
+	},
+	/// Change the category of recent entries, e.g. `amend --last 45m --category work`.
+	/// Manual entries and entries merged from other machines are left as they are.
+	/// The last few minutes (`main.sleep_minutes`) may not be saved by timeplot yet,
+	/// these keep their category
+	Amend(AmendOptions),
+}
+
+/// A time given on the command line, in local time zone unless it has a UTC offset:
+/// "2018-10-01", "2018-10-01 17:03", "17:03" (today) or "2018-10-01T17:03:27+02:00".
+pub fn parse_time_argument(value: &str) -> Result<DateTime<FixedOffset>, String> {
+	if let Ok(time) = log_entry::parse_time(value) {
+		return Ok(time);
+	}
+	let today = Local::now().date_naive();
+	let naive = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M")
+		.or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S"))
+		.or_else(|_| {
+			NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|d| d.and_time(NaiveTime::MIN))
+		})
+		.or_else(|_| NaiveTime::parse_from_str(value, "%H:%M").map(|t| today.and_time(t)))
+		.map_err(|_| format!("invalid time '{}', expected like 2018-10-01 17:03", value))?;
+	let time = Local
+		.from_local_datetime(&naive)
+		.earliest()
+		.ok_or_else(|| format!("time '{}' doesn't exist in local time zone", value))?;
+	Ok(time.with_timezone(&time.offset().fix()))
+}
+
+/// A duration given on the command line, like "45m", "2h", "1h30m" or "90s".
+pub fn parse_duration_argument(value: &str) -> Result<chrono::Duration, String> {
+	let invalid = || format!("invalid duration '{}', expected like 45m or 1h30m", value);
+	let mut seconds = 0;
+	let mut number = String::new();
+	for c in value.chars() {
+		if c.is_ascii_digit() {
+			number.push(c);
+			continue;
+		}
+		let unit = match c {
+			's' => 1,
+			'm' => 60,
+			'h' => 3600,
+			'd' => 24 * 3600,
+			_ => return Err(invalid()),
+		};
+		let count: i64 = number.parse().map_err(|_| invalid())?;
+		seconds += count * unit;
+		number.clear();
+	}
+	if !number.is_empty() || seconds == 0 {
+		return Err(invalid());
+	}
+	Ok(chrono::Duration::seconds(seconds))
+}
+
+#[derive(Debug)]
+pub enum ExportFormat {
+	Csv,
+	Json,
+	Ics,
+}
+
+impl std::str::FromStr for ExportFormat {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"csv" => Ok(ExportFormat::Csv),
+			"json" => Ok(ExportFormat::Json),
+			"ics" => Ok(ExportFormat::Ics),
+			_ => Err(format!("unknown export format {}", s)),
+		}
+	}
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ExportOptions {
+	/// Start of the range, like "2018-10-01" or "2018-10-01 17:03". By default, the whole log
+	#[structopt(long, parse(try_from_str = parse_time_argument))]
+	pub from: Option<DateTime<FixedOffset>>,
+	/// End of the range, exclusive. By default, now
+	#[structopt(long, parse(try_from_str = parse_time_argument))]
+	pub to: Option<DateTime<FixedOffset>>,
+	/// csv, json or ics (iCalendar, one event per session)
+	#[structopt(long, default_value = "csv", possible_values = &["csv", "json", "ics"])]
+	pub format: ExportFormat,
+	/// Merge consecutive entries of the same category and project into sessions
+	/// with category, start, end, duration and main title. Always on for ics
+	#[structopt(long)]
+	pub sessions: bool,
+	/// Write to a file instead of standard output
+	#[structopt(short, long, parse(from_os_str))]
+	pub output: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum ImportFormat {
+	ActivityWatch,
+	Arbtt,
+	Csv,
+}
+
+impl std::str::FromStr for ImportFormat {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"activitywatch" => Ok(ImportFormat::ActivityWatch),
+			"arbtt" => Ok(ImportFormat::Arbtt),
+			"csv" => Ok(ImportFormat::Csv),
+			_ => Err(format!("unknown import format {}", s)),
+		}
+	}
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ImportOptions {
+	/// activitywatch (bucket export JSON), arbtt (output of `arbtt-dump --format=json`) or csv
+	#[structopt(long, possible_values = &["activitywatch", "arbtt", "csv"])]
+	pub format: ImportFormat,
+	#[structopt(parse(from_os_str))]
+	pub file: PathBuf,
+	/// CSV column with the start time
+	#[structopt(long, default_value = "time")]
+	pub time_column: String,
+	/// CSV column with the duration in seconds
+	#[structopt(long, default_value = "duration")]
+	pub duration_column: String,
+	/// CSV column with the end time, instead of the duration
+	#[structopt(long)]
+	pub end_column: Option<String>,
+	/// CSV column with the window title or activity description
+	#[structopt(long, default_value = "title")]
+	pub title_column: String,
+	/// CSV column with the category, instead of assigning it by rules
+	#[structopt(long)]
+	pub category_column: Option<String>,
+	/// Format of CSV times in local time zone, like "%Y-%m-%d %H:%M:%S".
+	/// By default, times with UTC offset like 2018-10-01T17:03:27+02:00 are expected
+	#[structopt(long)]
+	pub time_format: Option<String>,
+	/// CSV field delimiter
+	#[structopt(long, default_value = ",")]
+	pub delimiter: char,
+	/// Only show what would be imported
+	#[structopt(long)]
+	pub dry_run: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct AmendOptions {
+	/// New category
+	#[structopt(long)]
+	pub category: String,
+	/// Entries of this long ago until now, like "45m" or "1h30m"
+	#[structopt(
+		long,
+		required_unless = "from",
+		conflicts_with_all = &["from", "to"],
+		parse(try_from_str = parse_duration_argument)
+	)]
+	pub last: Option<chrono::Duration>,
+	/// Start of the entries to change, like "14:00" (today) or "2018-10-01 14:00"
+	#[structopt(long, parse(try_from_str = parse_time_argument))]
+	pub from: Option<DateTime<FixedOffset>>,
+	/// End of the entries to change, exclusive. By default, now
+	#[structopt(long, parse(try_from_str = parse_time_argument))]
+	pub to: Option<DateTime<FixedOffset>>,
+	/// Only change entries with a window title containing this text (ignoring case).
+	/// An entry covers all windows of its interval, and is changed whole if any of them matches
+	#[structopt(long)]
+	pub title: Option<String>,
+	/// Only show the entries that would be changed
+	#[structopt(long)]
+	pub dry_run: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum LogCommand {
+	/// Convert log.log from the old space-separated format to the structured one.
+	/// The old file is kept as log.log.v1
+	Migrate,
+	/// Report log lines that can't be parsed, with their line numbers.
+	/// Such lines are skipped when plotting
+	Check,
+	/// Sort log.log by time, remove duplicates and unparsable lines,
+	/// and report unknown categories. The old file is backed up
+	Repair {
+		/// Only report what would be changed
+		#[structopt(long)]
+		dry_run: bool,
+	},
+	/// Copy all entries of log.log into the SQLite database, log.sqlite.
+	/// Set main.storage="sqlite" in config afterwards
+	ToSqlite,
+	/// Write all entries of the SQLite database to log.log, backing up the old file.
+	/// Set main.storage="text" in config afterwards
+	ToText,
+	/// Rewrite log.log and its monthly archives with all records encrypted
+	/// by the key configured in [encryption]
+	Encrypt,
+	/// Merge logs of other machines into this one's, tagging their entries with the host name.
+	/// Merging the same file again only adds what's new
+	Merge {
+		/// Log files, named after their host like laptop.log or laptop/log.log
+		#[structopt(required = true, parse(from_os_str))]
+		files: Vec<PathBuf>,
+		/// Host name to tag the entries with, instead of guessing it from the file name
+		#[structopt(long)]
+		host: Option<String>,
+	},
 }
 
 lazy_static! {
@@ -0,0 +1,176 @@
+use config::Config;
+use lazy_static::lazy_static;
+use log::debug;
+use log::warn;
+use std::env;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+const X11_SOCKET_DIR: &str = "/tmp/.X11-unix";
+
+/// How long discovered displays are used before looking again
+const DISCOVERY_TTL: Duration = Duration::from_secs(5 * 60);
+
+lazy_static! {
+	/// Displays found by `discover` and when, to not run loginctl for every sample
+	static ref DISCOVERED: Mutex<Option<(Instant, Vec<String>)>> = Mutex::new(None);
+}
+
+/// Displays to track, most likely active first.
+/// Needed when timeplot is started outside of the graphical session, e.g. from systemd.
+///
+/// Explicitly configured `main.displays` take precedence, then `$DISPLAY`,
+/// then graphical sessions known to logind, then X sockets in /tmp/.X11-unix.
+/// Discovered displays are reused for a few minutes, or until none of them works.
+pub fn displays(conf: &Config) -> Vec<String> {
+	if let Ok(displays) = conf.get::<Vec<String>>("main.displays") {
+		if !displays.is_empty() {
+			return displays;
+		}
+	}
+	if let Some(display) = env::var_os("DISPLAY") {
+		return vec![display.to_string_lossy().to_string()];
+	}
+	let mut discovered = DISCOVERED.lock().unwrap();
+	match &*discovered {
+		Some((time, displays)) if time.elapsed() < DISCOVERY_TTL => displays.clone(),
+		_ => {
+			let displays = discover();
+			*discovered = Some((Instant::now(), displays.clone()));
+			displays
+		}
+	}
+}
+
+/// Look for displays again on the next call, e.g. because none of them could be queried.
+pub fn forget_discovered() {
+	*DISCOVERED.lock().unwrap() = None;
+}
+
+fn discover() -> Vec<String> {
+	let mut result = logind_displays();
+	for display in socket_displays() {
+		if !result.contains(&display) {
+			result.push(display);
+		}
+	}
+	if result.is_empty() {
+		result.push(":0".to_string());
+	}
+	debug!("Discovered displays: {:?}", result);
+	result
+}
+
+/// The X authority file of the user's session, if it can be found.
+pub fn xauthority(home_dir: &Path) -> Option<PathBuf> {
+	let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+		.map(PathBuf::from)
+		.or_else(|| current_uid().map(|uid| PathBuf::from(format!("/run/user/{}", uid))));
+	let mut candidates = Vec::new();
+	if let Some(runtime_dir) = &runtime_dir {
+		// GDM
+		candidates.push(runtime_dir.join("gdm/Xauthority"));
+		// SDDM, mutter's Xwayland and others use randomized names
+		if let Ok(entries) = fs::read_dir(runtime_dir) {
+			let mut entries: Vec<_> = entries
+				.flatten()
+				.map(|e| e.path())
+				.filter(|path| {
+					let name = path.file_name().unwrap_or_default().to_string_lossy();
+					name.starts_with("xauth_") || name.starts_with(".mutter-Xwaylandauth.")
+				})
+				.collect();
+			entries.sort();
+			candidates.extend(entries);
+		}
+	}
+	candidates.push(home_dir.join(".Xauthority"));
+	candidates.into_iter().find(|path| path.is_file())
+}
+
+/// Owner of this process. Linux-specific, but so are X sockets.
+fn current_uid() -> Option<u32> {
+	fs::metadata("/proc/self").map(|m| m.uid()).ok()
+}
+
+/// Displays of this user's logind sessions, active ones first.
+fn logind_displays() -> Vec<String> {
+	let user = match env::var("USER") {
+		Ok(user) => user,
+		Err(_) => return Vec::new(),
+	};
+	let sessions = match Command::new("loginctl")
+		.arg("list-sessions")
+		.arg("--no-legend")
+		.output()
+	{
+		Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+		Err(err) => {
+			debug!("Failed to run loginctl, {}", err);
+			return Vec::new();
+		}
+	};
+	let mut displays: Vec<(bool, String)> = Vec::new();
+	for session in sessions.lines().filter_map(|l| l.split_whitespace().next()) {
+		let output = Command::new("loginctl")
+			.arg("show-session")
+			.arg(session)
+			.arg("-p")
+			.arg("Name")
+			.arg("-p")
+			.arg("Display")
+			.arg("-p")
+			.arg("Active")
+			.output();
+		let output = match output {
+			Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+			Err(err) => {
+				warn!("Failed to query logind session {}, {}", session, err);
+				continue;
+			}
+		};
+		let property = |name: &str| {
+			output
+				.lines()
+				.find_map(|l| l.strip_prefix(name)?.strip_prefix('='))
+				.unwrap_or("")
+				.to_string()
+		};
+		let display = property("Display");
+		if property("Name") == user && !display.is_empty() {
+			displays.push((property("Active") == "yes", display));
+		}
+	}
+	// stable sort keeps logind's order among equally active sessions
+	displays.sort_by_key(|(active, _)| !active);
+	displays.into_iter().map(|(_, display)| display).collect()
+}
+
+/// Displays with a socket in /tmp/.X11-unix, the ones owned by this user first.
+fn socket_displays() -> Vec<String> {
+	let uid = current_uid();
+	let entries = match fs::read_dir(X11_SOCKET_DIR) {
+		Ok(entries) => entries,
+		Err(_) => return Vec::new(),
+	};
+	let mut displays: Vec<(bool, u32)> = entries
+		.flatten()
+		.filter_map(|entry| {
+			let name = entry.file_name().to_string_lossy().to_string();
+			let number = name.strip_prefix('X')?.parse().ok()?;
+			let owned = entry.metadata().ok().map(|m| m.uid()) == uid;
+			Some((owned, number))
+		})
+		.collect();
+	displays.sort_by_key(|(owned, number)| (!owned, *number));
+	displays
+		.into_iter()
+		.map(|(_, number)| format!(":{}", number))
+		.collect()
+}
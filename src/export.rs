@@ -0,0 +1,251 @@
+use crate::command_line_interface::ExportFormat;
+use crate::command_line_interface::ExportOptions;
+use crate::log_entry::EntryKind;
+use crate::log_entry::LogEntry;
+use crate::storage;
+use crate::timeplot_constants::CONFIG_PARSE_ERROR;
+use crate::timeplot_constants::DATE_FORMAT;
+use chrono::prelude::*;
+use config::Config;
+use serde::Serialize;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+
+const ICS_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+/// Maximum length of an iCalendar content line, in bytes, excluding CRLF
+const ICS_LINE_LENGTH: usize = 75;
+
+/// An entry as a flat CSV row.
+#[derive(Serialize)]
+struct EntryRow<'a> {
+	time: String,
+	duration: Option<u64>,
+	kind: &'static str,
+	category: &'a str,
+	project: Option<&'a str>,
+	host: Option<&'a str>,
+	manual: bool,
+	events: Option<u64>,
+	title: &'a str,
+}
+
+impl<'a> EntryRow<'a> {
+	fn new(entry: &'a LogEntry) -> EntryRow<'a> {
+		EntryRow {
+			time: entry.time.format(DATE_FORMAT).to_string(),
+			duration: entry.duration,
+			kind: entry.kind.name(),
+			category: &entry.category,
+			project: entry.project.as_deref(),
+			host: entry.host.as_deref(),
+			manual: entry.manual,
+			events: entry.input_events,
+			title: &entry.title,
+		}
+	}
+}
+
+/// Consecutive activity of one category, without gaps longer than one iteration.
+#[derive(Serialize)]
+struct Session {
+	category: String,
+	project: Option<String>,
+	host: Option<String>,
+	#[serde(serialize_with = "serialize_time")]
+	start: DateTime<FixedOffset>,
+	#[serde(serialize_with = "serialize_time")]
+	end: DateTime<FixedOffset>,
+	/// Active seconds, which may be less than `end - start`
+	duration: u64,
+	/// The title seen for the longest time
+	title: String,
+}
+
+fn serialize_time<S: serde::Serializer>(
+	time: &DateTime<FixedOffset>,
+	serializer: S,
+) -> Result<S::Ok, S::Error> {
+	serializer.serialize_str(&time.format(DATE_FORMAT).to_string())
+}
+
+fn end_of(entry: &LogEntry, default_duration: u64) -> DateTime<FixedOffset> {
+	entry.time + chrono::Duration::seconds(entry.duration.unwrap_or(default_duration) as i64)
+}
+
+/// Merge activity entries into sessions of the same category, project and host.
+/// `entries` must be sorted by time.
+fn sessions(entries: &[LogEntry], interval_seconds: u64) -> Vec<Session> {
+	let max_gap = chrono::Duration::seconds(interval_seconds as i64);
+	let mut result = Vec::new();
+	// the session being built, with its seconds per title
+	let mut current: Option<(Session, Vec<(String, u64)>)> = None;
+	let finish = |(mut session, titles): (Session, Vec<(String, u64)>)| {
+		// `max_by_key` returns the last maximum, so iterate backwards to prefer the first one
+		if let Some((title, _)) = titles.into_iter().rev().max_by_key(|(_, s)| *s) {
+			session.title = title;
+		}
+		session
+	};
+	for entry in entries.iter().filter(|e| e.kind == EntryKind::Activity) {
+		let continues = current.as_ref().is_some_and(|(session, _)| {
+			session.category == entry.category
+				&& session.project == entry.project
+				&& session.host == entry.host
+				&& entry.time <= session.end + max_gap
+		});
+		if !continues {
+			result.extend(current.take().map(finish));
+			current = Some((
+				Session {
+					category: entry.category.clone(),
+					project: entry.project.clone(),
+					host: entry.host.clone(),
+					start: entry.time,
+					end: entry.time,
+					duration: 0,
+					title: String::new(),
+				},
+				Vec::new(),
+			));
+		}
+		let (session, titles) = current.as_mut().unwrap();
+		let duration = entry.duration.unwrap_or(interval_seconds);
+		session.end = session.end.max(end_of(entry, interval_seconds));
+		session.duration += duration;
+		let entry_titles: Vec<(&str, u64)> = if entry.titles.is_empty() {
+			vec![(&entry.title, duration)]
+		} else {
+			entry
+				.titles
+				.iter()
+				.map(|t| (t.title.as_str(), t.seconds))
+				.collect()
+		};
+		for (title, seconds) in entry_titles {
+			match titles.iter_mut().find(|(t, _)| t == title) {
+				Some((_, total)) => *total += seconds,
+				None => titles.push((title.to_string(), seconds)),
+			}
+		}
+	}
+	result.extend(current.map(finish));
+	result
+}
+
+fn write_csv<T: Serialize>(output: impl Write, rows: impl Iterator<Item = T>) -> io::Result<()> {
+	let mut writer = csv::Writer::from_writer(output);
+	for row in rows {
+		writer.serialize(row)?;
+	}
+	writer.flush()
+}
+
+fn write_json<T: Serialize>(mut output: impl Write, value: &T) -> io::Result<()> {
+	serde_json::to_writer_pretty(&mut output, value)?;
+	writeln!(output)
+}
+
+/// Escape TEXT values as in RFC 5545, 3.3.11
+fn ics_text(text: &str) -> String {
+	text.replace('\\', "\\\\")
+		.replace(';', "\\;")
+		.replace(',', "\\,")
+		.replace('\n', "\\n")
+}
+
+/// Write a content line, folded to lines of at most 75 bytes
+fn write_ics_line(output: &mut impl Write, line: &str) -> io::Result<()> {
+	let mut length = 0;
+	for c in line.chars() {
+		if length + c.len_utf8() > ICS_LINE_LENGTH {
+			output.write_all(b"\r\n ")?;
+			length = 1;
+		}
+		write!(output, "{}", c)?;
+		length += c.len_utf8();
+	}
+	output.write_all(b"\r\n")
+}
+
+fn write_ics(mut output: impl Write, sessions: &[Session]) -> io::Result<()> {
+	let utc = |time: &DateTime<FixedOffset>| time.with_timezone(&Utc).format(ICS_TIME_FORMAT);
+	let now = Utc::now().format(ICS_TIME_FORMAT);
+	write_ics_line(&mut output, "BEGIN:VCALENDAR")?;
+	write_ics_line(&mut output, "VERSION:2.0")?;
+	write_ics_line(&mut output, "PRODID:-//timeplot//timeplot export//EN")?;
+	for session in sessions {
+		let host = session.host.as_deref().unwrap_or("local");
+		let duration = format!(
+			"{}:{:02}h",
+			session.duration / 3600,
+			session.duration % 3600 / 60
+		);
+		let lines = [
+			"BEGIN:VEVENT".to_string(),
+			// stable, so that importing a newer export again updates the events
+			format!(
+				"UID:{}-{}-{}@timeplot",
+				session.start.timestamp(),
+				session.category,
+				host
+			),
+			format!("DTSTAMP:{}", now),
+			format!("DTSTART:{}", utc(&session.start)),
+			format!("DTEND:{}", utc(&session.end)),
+			format!("SUMMARY:{}", ics_text(&session.category)),
+			format!(
+				"DESCRIPTION:{}",
+				ics_text(&format!("{}\n{} active", session.title, duration))
+			),
+			format!("CATEGORIES:{}", ics_text(&session.category)),
+			"TRANSP:TRANSPARENT".to_string(),
+			"END:VEVENT".to_string(),
+		];
+		for line in &lines {
+			write_ics_line(&mut output, line)?;
+		}
+	}
+	write_ics_line(&mut output, "END:VCALENDAR")?;
+	output.flush()
+}
+
+/// Write the entries of a time range in the requested format.
+pub fn run(image_dir: &Path, conf: &Config, options: &ExportOptions) {
+	let from = options
+		.from
+		.map_or(0, |from| from.timestamp().max(0) as u64);
+	let to = options
+		.to
+		.map_or(Utc::now().timestamp(), |to| to.timestamp())
+		.max(0) as u64;
+	let mut storage = storage::open(image_dir, conf);
+	let mut entries = storage
+		.entries_since(from)
+		.unwrap_or_else(|err| panic!("{}", err))
+		.entries;
+	entries.retain(|entry| (from..to).contains(&entry.epoch_seconds()));
+
+	let output: Box<dyn Write> = match &options.output {
+		Some(file_path) => Box::new(BufWriter::new(
+			File::create(file_path)
+				.unwrap_or_else(|err| panic!("Failed to create {:?}, {}", file_path, err)),
+		)),
+		None => Box::new(BufWriter::new(io::stdout())),
+	};
+	let interval_seconds = (conf
+		.get_float("main.sleep_minutes")
+		.expect(CONFIG_PARSE_ERROR)
+		* 60.0) as u64;
+	let merged = || sessions(&entries, interval_seconds);
+	let result = match options.format {
+		ExportFormat::Ics => write_ics(output, &merged()),
+		ExportFormat::Csv if options.sessions => write_csv(output, merged().iter()),
+		ExportFormat::Json if options.sessions => write_json(output, &merged()),
+		ExportFormat::Csv => write_csv(output, entries.iter().map(EntryRow::new)),
+		ExportFormat::Json => write_json(output, &entries),
+	};
+	result.unwrap_or_else(|err| panic!("Failed to write export, {}", err));
+}
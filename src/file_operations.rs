@@ -6,8 +6,10 @@ extern crate fs2;
 extern crate gnuplot;
 extern crate open;
 
+use std::fs::File;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
+use std::io::SeekFrom;
 use std::ops::Not;
 use std::path::Path;
 
@@ -15,6 +17,7 @@ pub fn ensure_file(filename: &Path, content: &str) {
 	if Path::new(&filename).exists().not() {
 		let mut file = OpenOptions::new()
 			.create(true)
+			.truncate(true)
 			.write(true)
 			.open(filename)
 			.unwrap_or_else(|err| panic!("Failed to open or create file {:?}, {}", filename, err));
@@ -22,3 +25,19 @@ pub fn ensure_file(filename: &Path, content: &str) {
 			.unwrap_or_else(|err| panic!("failed to write to file {:?}, {}", filename, err));
 	}
 }
+
+/// Last non-empty line of a text file, or `None` if there is none.
+pub fn read_last_line(filename: &Path) -> Option<String> {
+	const TAIL_BYTES: u64 = 64 * 1024;
+	let mut file = File::open(filename).ok()?;
+	let len = file.metadata().ok()?.len();
+	file.seek(SeekFrom::Start(len.saturating_sub(TAIL_BYTES)))
+		.ok()?;
+	let mut tail = Vec::new();
+	file.read_to_end(&mut tail).ok()?;
+	let tail = String::from_utf8_lossy(&tail);
+	tail.lines()
+		.rev()
+		.find(|line| !line.trim().is_empty())
+		.map(str::to_string)
+}
@@ -0,0 +1,367 @@
+use crate::command_line_interface::ImportFormat;
+use crate::command_line_interface::ImportOptions;
+use crate::log_entry;
+use crate::log_entry::LogEntry;
+use crate::log_lock::LogLock;
+use crate::rules::Rules;
+use crate::sampling;
+use crate::sampling::Sample;
+use crate::storage;
+use crate::timeplot_constants::CONFIG_PARSE_ERROR;
+use crate::timeplot_constants::MAX_IDLE_SECONDS;
+use chrono::prelude::*;
+use config::Config;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// An activity as read from another tracker, before categorization.
+struct Activity {
+	time: DateTime<Utc>,
+	seconds: u64,
+	window_name: String,
+	/// Category given by the source, if any
+	category: Option<String>,
+}
+
+fn parse_rfc3339(value: &str) -> Result<DateTime<Utc>, String> {
+	DateTime::parse_from_rfc3339(value)
+		.map(|time| time.with_timezone(&Utc))
+		.map_err(|err| format!("invalid time '{}', {}", value, err))
+}
+
+/// ActivityWatch "Export bucket" / "Export all buckets" JSON.
+#[derive(Deserialize)]
+struct AwExport {
+	buckets: HashMap<String, AwBucket>,
+}
+
+#[derive(Deserialize)]
+struct AwBucket {
+	#[serde(rename = "type")]
+	kind: String,
+	events: Vec<AwEvent>,
+}
+
+#[derive(Deserialize)]
+struct AwEvent {
+	timestamp: String,
+	duration: f64,
+	data: HashMap<String, serde_json::Value>,
+}
+
+impl AwEvent {
+	fn text(&self, key: &str) -> &str {
+		self.data.get(key).and_then(|v| v.as_str()).unwrap_or("")
+	}
+}
+
+/// Window events of the `currentwindow` buckets, except for the time the AFK watcher saw no input.
+fn read_activitywatch(content: &str) -> Result<Vec<Activity>, String> {
+	let export: AwExport = serde_json::from_str(content).map_err(|err| err.to_string())?;
+	let mut afk = Vec::new();
+	for bucket in export.buckets.values().filter(|b| b.kind == "afkstatus") {
+		for event in bucket.events.iter().filter(|e| e.text("status") == "afk") {
+			let start = parse_rfc3339(&event.timestamp)?.timestamp() as f64;
+			afk.push((start, start + event.duration));
+		}
+	}
+	afk.sort_by(|a, b| a.partial_cmp(b).unwrap());
+	let mut events = Vec::new();
+	for bucket in export
+		.buckets
+		.values()
+		.filter(|b| b.kind == "currentwindow")
+	{
+		for event in bucket.events.iter().filter(|e| e.duration >= 1.0) {
+			let time = parse_rfc3339(&event.timestamp)?;
+			let middle = time.timestamp() as f64 + event.duration / 2.0;
+			events.push((middle, time, event));
+		}
+	}
+	events.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+	// both sorted by time, so AFK intervals are walked once along with the events
+	let mut afk = afk.into_iter().peekable();
+	let mut afk_end = f64::MIN;
+	let mut result = Vec::new();
+	for (middle, time, event) in events {
+		while let Some((_, end)) = afk.next_if(|(start, _)| *start <= middle) {
+			afk_end = afk_end.max(end);
+		}
+		if middle < afk_end {
+			continue;
+		}
+		let title = event.text("title");
+		let app = event.text("app");
+		let window_name = if title.to_lowercase().contains(&app.to_lowercase()) {
+			title.to_string()
+		} else {
+			format!("{} - {}", title, app)
+		};
+		result.push(Activity {
+			time,
+			seconds: event.duration as u64,
+			window_name,
+			category: None,
+		});
+	}
+	Ok(result)
+}
+
+/// A sample of `arbtt-dump --format=json`.
+#[derive(Deserialize)]
+struct ArbttSample {
+	date: String,
+	/// Milliseconds between samples
+	rate: u64,
+	/// Milliseconds without input
+	inactive: u64,
+	windows: Vec<ArbttWindow>,
+}
+
+#[derive(Deserialize)]
+struct ArbttWindow {
+	active: bool,
+	title: String,
+	program: String,
+}
+
+fn read_arbtt(content: &str) -> Result<Vec<Activity>, String> {
+	// a JSON list, or one sample per line
+	let samples: Vec<ArbttSample> = match serde_json::from_str(content) {
+		Ok(samples) => samples,
+		Err(_) => content
+			.lines()
+			.filter(|line| !line.trim().is_empty())
+			.map(|line| serde_json::from_str(line).map_err(|err| err.to_string()))
+			.collect::<Result<_, _>>()?,
+	};
+	let mut result = Vec::new();
+	for sample in samples {
+		if sample.inactive > MAX_IDLE_SECONDS as u64 * 1000 {
+			continue;
+		}
+		if let Some(window) = sample.windows.iter().find(|w| w.active) {
+			result.push(Activity {
+				time: parse_rfc3339(&sample.date)?,
+				seconds: sample.rate / 1000,
+				window_name: format!("{} - {}", window.title, window.program),
+				category: None,
+			});
+		}
+	}
+	Ok(result)
+}
+
+fn parse_csv_time(value: &str, format: Option<&str>) -> Result<DateTime<Utc>, String> {
+	match format {
+		Some(format) => {
+			let naive = NaiveDateTime::parse_from_str(value, format)
+				.map_err(|err| format!("invalid time '{}', {}", value, err))?;
+			Local
+				.from_local_datetime(&naive)
+				.earliest()
+				.map(|time| time.with_timezone(&Utc))
+				.ok_or_else(|| format!("time '{}' doesn't exist in local time zone", value))
+		}
+		None => parse_rfc3339(value).or_else(|err| {
+			log_entry::parse_time(value)
+				.map(|time| time.with_timezone(&Utc))
+				.map_err(|_| err)
+		}),
+	}
+}
+
+fn read_csv(content: &str, options: &ImportOptions) -> Result<Vec<Activity>, String> {
+	let mut reader = csv::ReaderBuilder::new()
+		.delimiter(options.delimiter as u8)
+		.from_reader(content.as_bytes());
+	let headers = reader.headers().map_err(|err| err.to_string())?.clone();
+	let column = |name: &str| {
+		headers
+			.iter()
+			.position(|h| h == name)
+			.ok_or_else(|| format!("no column '{}' in {:?}", name, headers))
+	};
+	let time_column = column(&options.time_column)?;
+	let title_column = column(&options.title_column)?;
+	let category_column = options.category_column.as_deref().map(column).transpose()?;
+	let end_column = options.end_column.as_deref().map(column).transpose()?;
+	let duration_column = match end_column {
+		Some(_) => None,
+		None => Some(column(&options.duration_column)?),
+	};
+	let time_format = options.time_format.as_deref();
+	let mut result = Vec::new();
+	for (index, record) in reader.records().enumerate() {
+		let record = record.map_err(|err| err.to_string())?;
+		let row_error = |err: String| format!("row {}: {}", index + 2, err);
+		let field = |column: usize| record.get(column).unwrap_or("").trim();
+		let time = parse_csv_time(field(time_column), time_format).map_err(row_error)?;
+		let seconds = match (end_column, duration_column) {
+			(Some(end_column), _) => {
+				let end = parse_csv_time(field(end_column), time_format).map_err(row_error)?;
+				(end - time).num_seconds().max(0) as u64
+			}
+			(None, Some(duration_column)) => {
+				let value = field(duration_column);
+				let seconds: f64 = value
+					.parse()
+					.map_err(|err| row_error(format!("invalid duration '{}', {}", value, err)))?;
+				seconds as u64
+			}
+			(None, None) => unreachable!(),
+		};
+		result.push(Activity {
+			time,
+			seconds,
+			window_name: field(title_column).to_string(),
+			category: category_column
+				.map(|column| field(column).to_string())
+				.filter(|category| !category.is_empty()),
+		});
+	}
+	Ok(result)
+}
+
+/// Merge activities into log entries of one `interval_seconds` each, like the ones timeplot writes.
+fn aggregate_activities(
+	activities: Vec<Activity>,
+	rules: &Rules,
+	interval_seconds: i64,
+) -> Vec<LogEntry> {
+	let mut samples: Vec<Sample> = activities
+		.into_iter()
+		.filter(|activity| activity.seconds > 0)
+		.map(|activity| {
+			let category = match activity.category {
+				Some(category) => category,
+				None => rules
+					.category(&activity.window_name)
+					.unwrap_or("skip")
+					.to_string(),
+			};
+			Sample {
+				time: activity.time,
+				seconds: activity.seconds,
+				category,
+				project: None,
+				input_events: None,
+				window_name: activity.window_name,
+			}
+		})
+		.collect();
+	samples.sort_by_key(|sample| sample.time);
+	let mut result = Vec::new();
+	let mut group: Vec<Sample> = Vec::new();
+	for sample in samples {
+		let group_ended = group
+			.first()
+			.is_some_and(|first| (sample.time - first.time).num_seconds() >= interval_seconds);
+		if group_ended {
+			result.extend(sampling::aggregate(&group));
+			group.clear();
+		}
+		group.push(sample);
+	}
+	result.extend(sampling::aggregate(&group));
+	result
+}
+
+/// Import history of another activity tracker into the configured storage.
+/// Entries that are already there are skipped, so importing the same file again adds nothing.
+pub fn run(image_dir: &Path, config_dir: &Path, conf: &Config, options: &ImportOptions) {
+	let content = fs::read_to_string(&options.file)
+		.unwrap_or_else(|err| panic!("Failed to read {:?}, {}", options.file, err));
+	let activities = match options.format {
+		ImportFormat::ActivityWatch => read_activitywatch(&content),
+		ImportFormat::Arbtt => read_arbtt(&content),
+		ImportFormat::Csv => read_csv(&content, options),
+	};
+	let activities = activities.unwrap_or_else(|err| {
+		eprintln!("Failed to read {:?}, {}", options.file, err);
+		std::process::exit(1);
+	});
+	let interval_seconds = (conf
+		.get_float("main.sleep_minutes")
+		.expect(CONFIG_PARSE_ERROR)
+		* 60.0) as i64;
+	let mut entries = aggregate_activities(activities, &Rules::load(config_dir), interval_seconds);
+
+	let _lock = LogLock::acquire(image_dir);
+	let mut storage = storage::open(image_dir, conf);
+	if let Some(first) = entries.first() {
+		let existing = storage
+			.entries_since(first.epoch_seconds())
+			.unwrap_or_else(|err| panic!("{}", err))
+			.entries;
+		log_entry::retain_new(&mut entries, &existing);
+	}
+	let (first, last) = match (entries.first(), entries.last()) {
+		(Some(first), Some(last)) => (first.time, last.time),
+		_ => {
+			println!("Nothing new to import from {:?}", options.file);
+			return;
+		}
+	};
+	let mut categories: HashMap<&str, u64> = HashMap::new();
+	for entry in &entries {
+		*categories.entry(&entry.category).or_default() += entry.duration.unwrap_or(0);
+	}
+	let mut categories: Vec<_> = categories.into_iter().collect();
+	categories.sort_unstable_by_key(|(_, seconds)| std::cmp::Reverse(*seconds));
+	println!("{} entries from {} to {}", entries.len(), first, last);
+	for (category, seconds) in categories {
+		println!(
+			"  {}: {}:{:02}h",
+			category,
+			seconds / 3600,
+			seconds % 3600 / 60
+		);
+	}
+	if options.dry_run {
+		println!("Dry run, nothing imported");
+		return;
+	}
+	storage
+		.insert(&entries)
+		.unwrap_or_else(|err| panic!("Failed to import, {}", err));
+	println!("Imported {} entries", entries.len());
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn activitywatch_afk_time_is_skipped() {
+		let window = |time: &str, title: &str| {
+			format!(
+				r#"{{"timestamp": "{}", "duration": 60, "data": {{"app": "x", "title": "{}"}}}}"#,
+				time, title
+			)
+		};
+		let content = format!(
+			r#"{{"buckets": {{
+				"aw-watcher-afk": {{"type": "afkstatus", "events": [
+					{{"timestamp": "2018-10-01T12:30:00Z", "duration": 600, "data": {{"status": "afk"}}}},
+					{{"timestamp": "2018-10-01T12:00:00Z", "duration": 3600, "data": {{"status": "not-afk"}}}},
+					{{"timestamp": "2018-10-01T12:05:00Z", "duration": 1200, "data": {{"status": "afk"}}}}
+				]}},
+				"aw-watcher-window": {{"type": "currentwindow", "events": [{}]}}
+			}}}}"#,
+			[
+				window("2018-10-01T12:35:00Z", "away"),
+				window("2018-10-01T12:01:00Z", "working"),
+				window("2018-10-01T12:10:00Z", "away"),
+				window("2018-10-01T12:20:00Z", "away"),
+				window("2018-10-01T12:50:00Z", "back"),
+			]
+			.join(",")
+		);
+		let activities = read_activitywatch(&content).unwrap();
+		let titles: Vec<_> = activities.iter().map(|a| a.window_name.as_str()).collect();
+		assert_eq!(titles, vec!["working - x", "back - x"]);
+	}
+}
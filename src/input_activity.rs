@@ -0,0 +1,84 @@
+use config::Config;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// Counts keyboard and mouse button events in the background.
+pub struct InputCounter {
+	events: Arc<AtomicU64>,
+	alive: Arc<AtomicBool>,
+}
+
+impl InputCounter {
+	/// Number of events since the previous call, or `None` if counting stopped working.
+	pub fn take(&self) -> Option<u64> {
+		if self.alive.load(Ordering::Relaxed) {
+			Some(self.events.swap(0, Ordering::Relaxed))
+		} else {
+			None
+		}
+	}
+}
+
+/// Start counting input events, if the platform supports it.
+///
+/// On Linux this listens to XInput2 raw events via `xinput test-xi2 --root`,
+/// which sees key presses and clicks regardless of which window has focus.
+/// Only the most likely active display is listened to.
+#[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
+pub fn start(conf: &Config) -> Option<InputCounter> {
+	use log::warn;
+	use std::io::BufRead;
+	use std::io::BufReader;
+	use std::process::Command;
+	use std::process::Stdio;
+
+	let display = crate::display::displays(conf).remove(0);
+	let child = Command::new("xinput")
+		.env("DISPLAY", display)
+		.arg("test-xi2")
+		.arg("--root")
+		.stdout(Stdio::piped())
+		.stderr(Stdio::null())
+		.spawn();
+	let mut child = match child {
+		Ok(child) => child,
+		Err(err) => {
+			warn!(
+				"Failed to run xinput, input activity won't be tracked. {}",
+				err
+			);
+			return None;
+		}
+	};
+	let stdout = child.stdout.take()?;
+	let events = Arc::new(AtomicU64::new(0));
+	let alive = Arc::new(AtomicBool::new(true));
+	let counter = InputCounter {
+		events: events.clone(),
+		alive: alive.clone(),
+	};
+	std::thread::spawn(move || {
+		for line in BufReader::new(stdout).lines() {
+			let line = match line {
+				Ok(line) => line,
+				Err(_) => break,
+			};
+			// e.g. "EVENT type 13 (RawKeyPress)"
+			if line.starts_with("EVENT type")
+				&& (line.ends_with("(RawKeyPress)") || line.ends_with("(RawButtonPress)"))
+			{
+				events.fetch_add(1, Ordering::Relaxed);
+			}
+		}
+		alive.store(false, Ordering::Relaxed);
+		warn!("xinput exited, input activity is no longer tracked");
+		let _ = child.wait();
+	});
+	Some(counter)
+}
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+pub fn start(_: &Config) -> Option<InputCounter> {
+	None
+}
@@ -0,0 +1,348 @@
+use crate::log_crypto;
+use crate::log_entry;
+use crate::log_entry::EntryKind;
+use crate::log_file;
+use crate::log_file::LogContents;
+use crate::log_file::LogFormat;
+use crate::log_lock::LogLock;
+use crate::log_merge;
+use crate::log_rotation;
+use crate::rules::Rules;
+use crate::storage;
+use crate::storage::SqliteStorage;
+use crate::storage::Storage;
+use crate::storage::TextStorage;
+use chrono::prelude::*;
+use config::Config;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Convert a legacy log to the structured format, keeping the original as a backup.
+pub fn migrate(image_dir: &Path) {
+	let _lock = LogLock::acquire(image_dir);
+	let file_path = log_file::log_path(image_dir);
+	match log_file::detect_format(&file_path) {
+		None => {
+			println!(
+				"Log {:?} is empty or missing, nothing to migrate",
+				file_path
+			);
+			return;
+		}
+		Some(LogFormat::Structured) => {
+			println!("Log {:?} is already in the current format", file_path);
+			return;
+		}
+		Some(LogFormat::Legacy) => {}
+	}
+	let contents = log_file::read_entries(&file_path);
+	if !contents.errors.is_empty() {
+		for error in &contents.errors {
+			eprintln!("{}", error);
+		}
+		eprintln!(
+			"Not migrating, {} lines could not be parsed. Fix or remove them first.",
+			contents.errors.len()
+		);
+		std::process::exit(1);
+	}
+	let entries = contents.entries;
+	let backup_path = file_path.with_extension("log.v1");
+	fs::copy(&file_path, &backup_path).unwrap_or_else(|err| {
+		panic!(
+			"Failed to back up {:?} to {:?}, {}",
+			file_path, backup_path, err
+		)
+	});
+	log_file::rewrite_entries(&file_path, &entries, LogFormat::Structured);
+	println!(
+		"Migrated {} entries of {:?}, the old log is kept as {:?}",
+		entries.len(),
+		file_path,
+		backup_path
+	);
+}
+
+/// Report lines of the log that can't be parsed. Exits with an error code if there are any.
+pub fn check(image_dir: &Path) {
+	let file_path = log_file::log_path(image_dir);
+	if log_file::detect_format(&file_path).is_none() {
+		println!("Log {:?} is empty or missing", file_path);
+		return;
+	}
+	let contents = log_file::read_entries(&file_path);
+	for error in &contents.errors {
+		println!("{}", error);
+	}
+	println!(
+		"{}: {} entries, {} bad lines",
+		file_path.display(),
+		contents.entries.len(),
+		contents.errors.len()
+	);
+	if !contents.errors.is_empty() {
+		std::process::exit(1);
+	}
+}
+
+/// Copy the file next to itself with a timestamp, returning the copy's path.
+/// Copy a file to e.g. `log.log.20181001-170327.bak` or `log.2018-10.log.gz.20181001-170327.bak`.
+fn backup(file_path: &Path) -> PathBuf {
+	let file_name = file_path.file_name().unwrap().to_string_lossy();
+	let backup_path = file_path.with_file_name(format!(
+		"{}.{}.bak",
+		file_name,
+		Local::now().format("%Y%m%d-%H%M%S")
+	));
+	fs::copy(file_path, &backup_path).unwrap_or_else(|err| {
+		panic!(
+			"Failed to back up {:?} to {:?}, {}",
+			file_path, backup_path, err
+		)
+	});
+	backup_path
+}
+
+/// Categories that are configured in config.toml or assigned by any rule.
+pub fn known_categories(config_dir: &Path, conf: &Config) -> BTreeSet<String> {
+	let mut result: BTreeSet<String> = conf
+		.get_table("category")
+		.map(|table| table.into_keys().collect())
+		.unwrap_or_default();
+	result.extend(Rules::load(config_dir).categories().map(str::to_string));
+	result
+}
+
+/// Number of single-character edits to turn one string into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+	let b: Vec<char> = b.chars().collect();
+	let mut previous: Vec<usize> = (0..=b.len()).collect();
+	for (i, ca) in a.chars().enumerate() {
+		let mut current = vec![i + 1];
+		for (j, cb) in b.iter().enumerate() {
+			let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+			current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+		}
+		previous = current;
+	}
+	previous[b.len()]
+}
+
+/// Sort the log by time, remove exact duplicates and lines that can't be parsed,
+/// and report categories that are neither configured nor assigned by any rule.
+/// Monthly archives are repaired the same way as log.log.
+/// Each changed file is backed up, and removed bad lines are saved to log.log.rejected.
+pub fn repair(image_dir: &Path, config_dir: &Path, conf: &Config, dry_run: bool) {
+	let _lock = LogLock::acquire(image_dir);
+	let file_path = log_file::log_path(image_dir);
+	let rejected_path = file_path.with_extension("log.rejected");
+	let mut files: Vec<FileContents> = Vec::new();
+	for archive in log_rotation::archives_since(image_dir, 0) {
+		let mut contents = LogContents::default();
+		match log_rotation::read_archive(&archive, &mut contents) {
+			Ok(()) => files.push((archive, None, contents)),
+			Err(err) => println!("Skipping {:?}, failed to read it, {}", archive, err),
+		}
+	}
+	if let Some(format) = log_file::detect_format(&file_path) {
+		let contents = log_file::read_entries(&file_path);
+		files.push((file_path.clone(), Some(format), contents));
+	}
+	if files.is_empty() {
+		println!("Log {:?} is empty or missing, nothing to repair", file_path);
+		return;
+	}
+
+	let known = known_categories(config_dir, conf);
+	let mut unknown: BTreeMap<String, usize> = BTreeMap::new();
+	for (_, _, contents) in &files {
+		for entry in &contents.entries {
+			if entry.kind == EntryKind::Activity && !known.contains(&entry.category) {
+				*unknown.entry(entry.category.clone()).or_default() += 1;
+			}
+		}
+	}
+	for (category, count) in &unknown {
+		let suggestion = known
+			.iter()
+			.map(|k| (edit_distance(category, k), k))
+			.filter(|(distance, _)| *distance <= 2)
+			.min();
+		match suggestion {
+			Some((_, known)) => println!(
+				"Unknown category '{}' in {} entries, did you mean '{}'?",
+				category, count, known
+			),
+			None => println!(
+				"Unknown category '{}' in {} entries, it's not in config or rules",
+				category, count
+			),
+		}
+	}
+
+	for (file_path, format, contents) in files {
+		repair_file(&file_path, format, contents, &rejected_path, dry_run);
+	}
+}
+
+/// A log file or archive with its format (`None` for archives, which are rewritten as they are)
+/// and what was read from it.
+type FileContents = (PathBuf, Option<LogFormat>, LogContents);
+
+/// Sort one file of the log and remove its duplicates and unparsable lines.
+fn repair_file(
+	file_path: &Path,
+	format: Option<LogFormat>,
+	contents: LogContents,
+	rejected_path: &Path,
+	dry_run: bool,
+) {
+	for error in &contents.errors {
+		println!("Removing {}", error);
+	}
+	let mut entries = contents.entries;
+	let unsorted = entries.windows(2).filter(|w| w[0].time > w[1].time).count();
+	// stable, so that entries with the same time keep their order
+	entries.sort_by_key(|entry| entry.time);
+	let mut seen = HashSet::new();
+	let before_deduplication = entries.len();
+	entries.retain(|entry| seen.insert(log_entry::format_json_line(entry)));
+	let duplicates = before_deduplication - entries.len();
+
+	if contents.errors.is_empty() && unsorted == 0 && duplicates == 0 {
+		println!("Log {:?} is in order, nothing to repair", file_path);
+		return;
+	}
+	println!(
+		"{:?}: {} bad lines removed, {} entries out of order, {} duplicates removed",
+		file_path,
+		contents.errors.len(),
+		unsorted,
+		duplicates
+	);
+	if dry_run {
+		println!("Dry run, the log is not changed");
+		return;
+	}
+	let backup_path = backup(file_path);
+	if !contents.errors.is_empty() {
+		let mut rejected = OpenOptions::new()
+			.append(true)
+			.create(true)
+			.open(rejected_path)
+			.unwrap_or_else(|err| panic!("Failed to open {:?}, {}", rejected_path, err));
+		for error in &contents.errors {
+			writeln!(rejected, "{}", error.line)
+				.unwrap_or_else(|err| panic!("Failed to write to {:?}, {}", rejected_path, err));
+		}
+	}
+	match format {
+		Some(format) => log_file::rewrite_entries(file_path, &entries, format),
+		None => log_rotation::write_archive(file_path, &entries),
+	}
+	println!(
+		"Repaired {:?}, the old file is kept as {:?}",
+		file_path, backup_path
+	);
+}
+
+/// Copy the text log, including monthly archives, into an empty SQLite database.
+pub fn to_sqlite(image_dir: &Path, conf: &Config) {
+	let _lock = LogLock::acquire(image_dir);
+	let contents = TextStorage::new(image_dir, conf)
+		.entries_since(0)
+		.unwrap_or_else(|err| panic!("{}", err));
+	if !contents.errors.is_empty() {
+		for error in &contents.errors {
+			eprintln!("{}", error);
+		}
+		eprintln!(
+			"Not copying, {} lines could not be parsed. Run `timeplot log repair` first.",
+			contents.errors.len()
+		);
+		std::process::exit(1);
+	}
+	let mut database = SqliteStorage::open(image_dir);
+	let existing = database.count();
+	if existing > 0 {
+		eprintln!(
+			"Not copying, the database already has {} entries. Remove it or use `timeplot log to-text`.",
+			existing
+		);
+		std::process::exit(1);
+	}
+	database
+		.append_all(&contents.entries)
+		.unwrap_or_else(|err| panic!("{}", err));
+	println!(
+		"Copied {} entries to the database. Set main.storage=\"sqlite\" in config to use it.",
+		contents.entries.len()
+	);
+}
+
+/// Write all entries of the SQLite database to the text log, backing up the existing one.
+pub fn to_text(image_dir: &Path) {
+	let _lock = LogLock::acquire(image_dir);
+	let archives = log_rotation::archives_since(image_dir, 0);
+	if !archives.is_empty() {
+		eprintln!(
+			"Not writing, monthly archives of the text log exist and would duplicate entries: {:?}",
+			archives
+		);
+		std::process::exit(1);
+	}
+	let mut database = SqliteStorage::open(image_dir);
+	let entries = database
+		.entries_since(0)
+		.unwrap_or_else(|err| panic!("{}", err))
+		.entries;
+	let file_path = log_file::log_path(image_dir);
+	if log_file::detect_format(&file_path).is_some() {
+		let backup_path = backup(&file_path);
+		println!("The old log is kept as {:?}", backup_path);
+	}
+	log_file::rewrite_entries(&file_path, &entries, LogFormat::Structured);
+	println!(
+		"Wrote {} entries to {:?}. Set main.storage=\"text\" in config to use it.",
+		entries.len(),
+		file_path
+	);
+}
+
+/// Encrypt existing records of the text log, which are otherwise only encrypted when written.
+pub fn encrypt(image_dir: &Path, conf: &Config) {
+	if !log_crypto::has_key() {
+		eprintln!(
+			"No key configured, set encryption.keyfile or encryption.passphrase_command in config"
+		);
+		std::process::exit(1);
+	}
+	let _lock = LogLock::acquire(image_dir);
+	let count = TextStorage::new(image_dir, conf).rewrite_range(0, u64::MAX, &mut |_| true);
+	println!("Encrypted {} records", count);
+}
+
+/// Merge logs of other machines into the configured storage.
+pub fn merge(image_dir: &Path, conf: &Config, files: &[PathBuf], host: Option<&str>) {
+	let _lock = LogLock::acquire(image_dir);
+	let mut storage = storage::open(image_dir, conf);
+	let mut failed = false;
+	for file in files {
+		match log_merge::merge_file(storage.as_mut(), conf, file, host, 0) {
+			Ok(merged) => println!("Merged {} new entries from {:?}", merged.added, file),
+			Err(err) => {
+				eprintln!("Failed to merge {:?}, {}", file, err);
+				failed = true;
+			}
+		}
+	}
+	if failed {
+		std::process::exit(1);
+	}
+}
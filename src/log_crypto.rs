@@ -0,0 +1,239 @@
+use crate::timeplot_constants::DATE_FORMAT;
+use crate::timeplot_constants::SALT_FILE_NAME;
+use crate::timeplot_constants::TITLE_KEY_FILE_NAME;
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::aead::AeadCore;
+use chacha20poly1305::aead::KeyInit;
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::aead::Payload;
+use chacha20poly1305::ChaCha20Poly1305;
+use chacha20poly1305::Key;
+use chacha20poly1305::Nonce;
+use chrono::DateTime;
+use chrono::FixedOffset;
+use config::Config;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::sync::RwLock;
+
+const NONCE_LENGTH: usize = 12;
+const SALT_LENGTH: usize = 16;
+
+lazy_static! {
+	/// Key for log records, set once at startup if `[encryption]` is configured
+	static ref KEY: RwLock<Option<Key>> = RwLock::new(None);
+}
+
+#[cfg(test)]
+thread_local! {
+	/// Tests run in parallel threads, so a test's key must not leak into the others
+	static TEST_KEY: std::cell::Cell<Option<Key>> = const { std::cell::Cell::new(None) };
+}
+
+#[cfg(not(test))]
+fn key() -> Option<Key> {
+	*KEY.read().unwrap()
+}
+
+#[cfg(test)]
+fn key() -> Option<Key> {
+	TEST_KEY.with(|key| key.get())
+}
+
+#[cfg(not(test))]
+fn set_key(key: Key) {
+	*KEY.write().unwrap() = Some(key);
+}
+
+#[cfg(test)]
+fn set_key(key: Key) {
+	TEST_KEY.with(|test_key| test_key.set(Some(key)));
+}
+
+/// A log record encrypted as a whole:
+/// `{"encrypted":"<base64 of nonce and ciphertext>","time":"<time of the entry>"}`.
+/// The time is authenticated along with the ciphertext, so a record can't be given another time,
+/// and it has to match the time of the decrypted entry.
+#[derive(Serialize, Deserialize)]
+struct EncryptedLine {
+	encrypted: String,
+	time: String,
+}
+
+/// Random salt for passphrase-derived keys, kept next to the log so that backups include it.
+fn salt(image_dir: &Path) -> Vec<u8> {
+	stored_random(&image_dir.join(SALT_FILE_NAME), 0o644)
+}
+
+/// Random per-install key for hashing window titles. It's kept in the config directory,
+/// readable only by the user, so that copies of the log don't include it.
+pub fn title_key(config_dir: &Path) -> Vec<u8> {
+	stored_random(&config_dir.join(TITLE_KEY_FILE_NAME), 0o600)
+}
+
+/// Random bytes read from `file_path`, created with the Unix permissions `mode` on first use.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn stored_random(file_path: &Path, mode: u32) -> Vec<u8> {
+	if let Ok(bytes) = fs::read(file_path) {
+		return bytes;
+	}
+	let mut bytes = vec![0; SALT_LENGTH];
+	OsRng.fill_bytes(&mut bytes);
+	let mut options = OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+	options
+		.open(file_path)
+		.and_then(|mut file| file.write_all(&bytes))
+		.unwrap_or_else(|err| panic!("Failed to write {:?}, {}", file_path, err));
+	bytes
+}
+
+fn run_passphrase_command(command: &[String]) -> Vec<u8> {
+	let executable = command
+		.first()
+		.expect("encryption.passphrase_command must not be empty");
+	let output = Command::new(executable)
+		.args(&command[1..])
+		.output()
+		.unwrap_or_else(|err| panic!("Failed to run passphrase command {:?}, {}", command, err));
+	if !output.status.success() {
+		panic!(
+			"Passphrase command {:?} failed with {}",
+			command, output.status
+		);
+	}
+	let mut passphrase = output.stdout;
+	while passphrase.last() == Some(&b'\n') || passphrase.last() == Some(&b'\r') {
+		passphrase.pop();
+	}
+	if passphrase.is_empty() {
+		panic!("Passphrase command {:?} printed nothing", command);
+	}
+	passphrase
+}
+
+/// Read the key configured in `[encryption]`, if any. From then on,
+/// written log records are encrypted and encrypted records are decrypted when read.
+pub fn init(conf: &Config, image_dir: &Path) {
+	let key = if let Ok(keyfile) = conf.get_string("encryption.keyfile") {
+		let content = fs::read(&keyfile).unwrap_or_else(|err| {
+			panic!("Failed to read encryption key file {}, {}", keyfile, err)
+		});
+		Key::clone_from_slice(&Sha256::digest(&content))
+	} else if let Ok(command) = conf.get::<Vec<String>>("encryption.passphrase_command") {
+		let passphrase = run_passphrase_command(&command);
+		let mut key = Key::default();
+		Argon2::default()
+			.hash_password_into(&passphrase, &salt(image_dir), &mut key)
+			.unwrap_or_else(|err| panic!("Failed to derive key from passphrase, {}", err));
+		key
+	} else {
+		return;
+	};
+	set_key(key);
+}
+
+pub fn has_key() -> bool {
+	key().is_some()
+}
+
+pub fn is_encrypted(line: &str) -> bool {
+	line.starts_with("{\"encrypted\":")
+}
+
+/// Encrypt the log line of an entry at `time` if a key is configured, otherwise return it as is.
+pub fn encrypt_line(line: &str, time: &DateTime<FixedOffset>) -> String {
+	let key = match key() {
+		Some(key) => key,
+		None => return line.to_string(),
+	};
+	let time = time.format(DATE_FORMAT).to_string();
+	let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+	let payload = Payload {
+		msg: line.as_bytes(),
+		aad: time.as_bytes(),
+	};
+	let ciphertext = ChaCha20Poly1305::new(&key)
+		.encrypt(&nonce, payload)
+		.expect("Failed to encrypt log line");
+	let mut data = nonce.to_vec();
+	data.extend(ciphertext);
+	serde_json::to_string(&EncryptedLine {
+		encrypted: BASE64.encode(data),
+		time,
+	})
+	.expect("Failed to serialize encrypted line")
+}
+
+/// Time and plain text of an encrypted log line.
+pub fn decrypt_line(line: &str) -> Result<(String, String), String> {
+	let key = key()
+		.ok_or_else(|| "encrypted record, but no key is configured in [encryption]".to_string())?;
+	let line: EncryptedLine = serde_json::from_str(line).map_err(|err| err.to_string())?;
+	let data = BASE64
+		.decode(line.encrypted)
+		.map_err(|err| format!("invalid encrypted record, {}", err))?;
+	if data.len() < NONCE_LENGTH {
+		return Err("invalid encrypted record, too short".to_string());
+	}
+	let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+	let payload = Payload {
+		msg: ciphertext,
+		aad: line.time.as_bytes(),
+	};
+	let plaintext = ChaCha20Poly1305::new(&key)
+		.decrypt(Nonce::from_slice(nonce), payload)
+		.map_err(|_| "failed to decrypt record, wrong key or damaged record".to_string())?;
+	let plaintext = String::from_utf8(plaintext).map_err(|err| err.to_string())?;
+	Ok((line.time, plaintext))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[cfg(unix)]
+	#[test]
+	fn title_key_is_private_and_kept() {
+		use std::os::unix::fs::PermissionsExt;
+		let config_dir =
+			std::env::temp_dir().join(format!("timeplot-{}-title-key", std::process::id()));
+		fs::create_dir_all(&config_dir).unwrap();
+		let key = title_key(&config_dir);
+		let mode = fs::metadata(config_dir.join(TITLE_KEY_FILE_NAME))
+			.unwrap()
+			.permissions()
+			.mode();
+		let again = title_key(&config_dir);
+		fs::remove_dir_all(&config_dir).unwrap();
+		assert_eq!(mode & 0o777, 0o600);
+		assert_eq!(key, again);
+	}
+
+	#[test]
+	fn record_time_is_authenticated() {
+		set_key(Key::clone_from_slice(&[7; 32]));
+		let time = DateTime::parse_from_rfc3339("2018-10-01T17:03:27+02:00").unwrap();
+		let line = encrypt_line("2018-10-01T17:03:27+02:00 work Editor", &time);
+		assert!(is_encrypted(&line));
+		let (decrypted_time, plaintext) = decrypt_line(&line).unwrap();
+		assert_eq!(decrypted_time, "2018-10-01T17:03:27+02:00");
+		assert_eq!(plaintext, "2018-10-01T17:03:27+02:00 work Editor");
+		let moved = line.replace("17:03:27", "18:03:27");
+		assert!(decrypt_line(&moved).is_err());
+	}
+}
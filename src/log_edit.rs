@@ -0,0 +1,194 @@
+use crate::command_line_interface::AmendOptions;
+use crate::log_commands;
+use crate::log_entry;
+use crate::log_entry::EntryKind;
+use crate::log_entry::LogEntry;
+use crate::log_lock::LogLock;
+use crate::storage;
+use crate::timeplot_constants::CONFIG_PARSE_ERROR;
+use chrono::prelude::*;
+use config::Config;
+use log::warn;
+use std::path::Path;
+
+fn warn_if_unknown(config_dir: &Path, conf: &Config, category: &str) {
+	if !log_commands::known_categories(config_dir, conf).contains(category) {
+		warn!(
+			"Category {} is neither in your rules nor in [category] of config",
+			category
+		);
+	}
+}
+
+/// Record a manual entry between `from` and `to`. It's split into entries of one iteration each,
+/// so that it's plotted like observed activity.
+pub fn add(
+	image_dir: &Path,
+	config_dir: &Path,
+	conf: &Config,
+	category: &str,
+	from: DateTime<FixedOffset>,
+	to: DateTime<FixedOffset>,
+	note: Option<&str>,
+) {
+	if from >= to {
+		eprintln!("The end {} must be after the start {}", to, from);
+		std::process::exit(1);
+	}
+	// entries are in the past, and the daemon decides what happened since the last one
+	if to > Utc::now() {
+		eprintln!(
+			"The end {} is in the future, add the entry once it's over",
+			to
+		);
+		std::process::exit(1);
+	}
+	warn_if_unknown(config_dir, conf, category);
+	let interval_seconds = (conf
+		.get_float("main.sleep_minutes")
+		.expect(CONFIG_PARSE_ERROR)
+		* 60.0) as i64;
+	let mut entries = Vec::new();
+	let mut start = from.timestamp();
+	while start < to.timestamp() {
+		let end = (start + interval_seconds).min(to.timestamp());
+		entries.push(LogEntry {
+			time: log_entry::local_time(start),
+			duration: Some((end - start) as u64),
+			category: category.to_string(),
+			manual: true,
+			title: note.unwrap_or_default().to_string(),
+			..Default::default()
+		});
+		start = end;
+	}
+
+	let _lock = LogLock::acquire(image_dir);
+	let mut storage = storage::open(image_dir, conf);
+	let existing = storage
+		.entries_since(from.timestamp() as u64)
+		.unwrap_or_else(|err| panic!("{}", err))
+		.entries;
+	log_entry::retain_new(&mut entries, &existing);
+	if entries.is_empty() {
+		println!("Already recorded");
+		return;
+	}
+	storage.insert(&entries).unwrap_or_else(|err| {
+		eprintln!("Failed to add the entry, {}", err);
+		std::process::exit(1);
+	});
+	println!(
+		"Added {} from {} to {}",
+		category,
+		from.format("%Y-%m-%d %H:%M"),
+		to.format("%Y-%m-%d %H:%M")
+	);
+}
+
+/// Record a marker with `text`, at `at` or now.
+pub fn mark(image_dir: &Path, conf: &Config, text: &str, at: Option<DateTime<FixedOffset>>) {
+	if text.trim().is_empty() {
+		eprintln!("The marker text is empty");
+		std::process::exit(1);
+	}
+	let time = at.unwrap_or_else(|| log_entry::local_time(Utc::now().timestamp()));
+	// like entries, markers are added once it happened
+	if time > Utc::now() {
+		eprintln!(
+			"The time {} is in the future, add the marker once it happened",
+			time
+		);
+		std::process::exit(1);
+	}
+	let marker = LogEntry {
+		time,
+		kind: EntryKind::Marker,
+		category: "marker".to_string(),
+		manual: true,
+		title: text.to_string(),
+		..Default::default()
+	};
+	let _lock = LogLock::acquire(image_dir);
+	storage::open(image_dir, conf)
+		.insert(&[marker])
+		.unwrap_or_else(|err| {
+			eprintln!("Failed to add the marker, {}", err);
+			std::process::exit(1);
+		});
+	println!("Marked {} at {}", text, time.format("%Y-%m-%d %H:%M"));
+}
+
+/// Whether `amend` changes the entry: activity of this machine that wasn't entered by hand,
+/// starting in `from..to`, with a title containing `title` (lowercase) if given.
+/// An entry holds all windows of its interval, it changes as a whole if any of them matches.
+fn amendable(entry: &LogEntry, from: u64, to: u64, title: Option<&str>, category: &str) -> bool {
+	let matches_title = |pattern: &str| {
+		let titles = entry.titles.iter().map(|t| t.title.as_str());
+		std::iter::once(entry.title.as_str())
+			.chain(titles)
+			.any(|t| t.to_lowercase().contains(pattern))
+	};
+	entry.kind == EntryKind::Activity
+		&& !entry.manual
+		&& entry.host.is_none()
+		&& entry.category != category
+		&& (from..to).contains(&entry.epoch_seconds())
+		&& title.is_none_or(matches_title)
+}
+
+/// Change the category of entries in a time range, holding the log lock
+/// so that the daemon doesn't append meanwhile.
+/// Samples the daemon hasn't saved yet (up to `main.sleep_minutes`) aren't in the log,
+/// they are saved later with the category they had.
+pub fn amend(image_dir: &Path, config_dir: &Path, conf: &Config, options: &AmendOptions) {
+	let now = Utc::now().timestamp();
+	let (from, to) = match (options.last, options.from) {
+		(Some(last), _) => (now - last.num_seconds(), now),
+		(None, Some(from)) => (
+			from.timestamp(),
+			options.to.map_or(now, |to| to.timestamp()),
+		),
+		(None, None) => unreachable!("--last or --from is required"),
+	};
+	let (from, to) = (from.max(0) as u64, to.max(0) as u64);
+	let category = options.category.as_str();
+	let title = options.title.as_deref().map(str::to_lowercase);
+	let title = title.as_deref();
+	warn_if_unknown(config_dir, conf, category);
+
+	let _lock = LogLock::acquire(image_dir);
+	let mut storage = storage::open(image_dir, conf);
+	if options.dry_run {
+		let entries = storage
+			.entries_since(from)
+			.unwrap_or_else(|err| panic!("{}", err))
+			.entries;
+		let matching: Vec<_> = entries
+			.iter()
+			.filter(|entry| amendable(entry, from, to, title, category))
+			.collect();
+		for entry in &matching {
+			println!(
+				"{} {} {}",
+				entry.time.format("%Y-%m-%d %H:%M"),
+				entry.category,
+				entry.title
+			);
+		}
+		println!(
+			"Dry run, {} entries would be changed to {}",
+			matching.len(),
+			category
+		);
+		return;
+	}
+	let changed = storage.rewrite_range(from, to, &mut |entry| {
+		if !amendable(entry, from, to, title, category) {
+			return false;
+		}
+		entry.category = category.to_string();
+		true
+	});
+	println!("Changed {} entries to {}", changed, category);
+}
@@ -0,0 +1,449 @@
+use crate::log_crypto;
+use crate::timeplot_constants::DATE_FORMAT;
+use crate::timeplot_constants::LEGACY_DATE_FORMAT;
+use chrono::prelude::*;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use std::collections::HashSet;
+
+/// Version of the structured (JSON lines) log format.
+/// Version 1 is the legacy space-separated format, which has no header.
+pub const LOG_FORMAT_VERSION: u32 = 2;
+
+/// Attribute keys that may follow the category in a log line, as `key=value`.
+/// Only these are recognized, everything else is considered part of the title.
+/// Lines with a legacy timestamp were written before attributes existed, so they have none.
+const ATTRIBUTE_KEYS: &[&str] = &[
+	"kind", "duration", "project", "host", "manual", "events", "titles",
+];
+
+/// Written between the attributes and a title that would otherwise be read as attributes,
+/// like "duration=5" or "-- foo".
+const END_OF_ATTRIBUTES: &str = "--";
+
+/// First line of a structured log, e.g. `{"timeplot_log":2}`.
+#[derive(Serialize, Deserialize)]
+struct LogHeader {
+	timeplot_log: u32,
+}
+
+/// A single line of the activity log.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct LogEntry {
+	/// Local time of the entry, keeping the UTC offset it was written with
+	#[serde(
+		serialize_with = "serialize_time",
+		deserialize_with = "deserialize_time"
+	)]
+	pub time: DateTime<FixedOffset>,
+	/// Measured length of the entry in seconds.
+	/// Older entries don't have it, and last until the next entry.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub duration: Option<u64>,
+	#[serde(default, skip_serializing_if = "EntryKind::is_activity")]
+	pub kind: EntryKind,
+	pub category: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub project: Option<String>,
+	/// Machine the entry was recorded on, set when logs of several machines are merged.
+	/// Entries without it are from this machine.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub host: Option<String>,
+	/// Entered by hand with `timeplot add` rather than observed.
+	/// Such entries are never changed by automatic rewrites
+	#[serde(default, skip_serializing_if = "is_false")]
+	pub manual: bool,
+	/// Keyboard and mouse button events during the entry, if tracked
+	#[serde(default, rename = "events", skip_serializing_if = "Option::is_none")]
+	pub input_events: Option<u64>,
+	/// All window titles seen during the entry, if there was more than one
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub titles: Vec<TitleDuration>,
+	#[serde(default, skip_serializing_if = "String::is_empty")]
+	pub title: String,
+}
+
+impl LogEntry {
+	pub fn epoch_seconds(&self) -> u64 {
+		self.time.timestamp() as u64
+	}
+
+	/// What makes two entries the same record. Titles aren't part of it,
+	/// since retention hashes or drops them in one copy but not in the other.
+	fn identity(
+		&self,
+	) -> (
+		u64,
+		Option<String>,
+		EntryKind,
+		String,
+		Option<String>,
+		Option<u64>,
+	) {
+		(
+			self.epoch_seconds(),
+			self.host.clone(),
+			self.kind,
+			self.category.clone(),
+			self.project.clone(),
+			self.duration,
+		)
+	}
+}
+
+/// Drop entries that are already in `existing`. Entries aren't compared with each other,
+/// since different records may start in the same second.
+pub fn retain_new(entries: &mut Vec<LogEntry>, existing: &[LogEntry]) {
+	let existing: HashSet<_> = existing.iter().map(LogEntry::identity).collect();
+	entries.retain(|entry| !existing.contains(&entry.identity()));
+}
+
+/// The moment in the local timezone (with the UTC offset in effect at that moment).
+pub fn local_time(epoch_seconds: i64) -> DateTime<FixedOffset> {
+	let time = Local.timestamp_opt(epoch_seconds, 0).unwrap();
+	// not `.into()`, which converts to UTC
+	time.with_timezone(&time.offset().fix())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+	/// Window activity, observed by the daemon
+	#[default]
+	Activity,
+	/// The computer was suspended or shut down from this moment until the next entry
+	Offline,
+	/// A note about a moment, like "started new medication", with the text as title
+	Marker,
+}
+
+fn is_false(value: &bool) -> bool {
+	!value
+}
+
+impl EntryKind {
+	fn is_activity(&self) -> bool {
+		*self == EntryKind::Activity
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			EntryKind::Activity => "activity",
+			EntryKind::Offline => "offline",
+			EntryKind::Marker => "marker",
+		}
+	}
+}
+
+impl std::str::FromStr for EntryKind {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"activity" => Ok(EntryKind::Activity),
+			"offline" => Ok(EntryKind::Offline),
+			"marker" => Ok(EntryKind::Marker),
+			_ => Err(format!("unknown entry kind {}", s)),
+		}
+	}
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TitleDuration {
+	pub seconds: u64,
+	pub title: String,
+}
+
+/// Percent-encode characters that would break the space-separated line format.
+fn escape_value(value: &str) -> String {
+	let mut result = String::with_capacity(value.len());
+	for c in value.chars() {
+		match c {
+			'%' | ' ' | '=' | ',' | ':' | '\n' | '\t' => {
+				result.push_str(&format!("%{:02X}", c as u32))
+			}
+			_ => result.push(c),
+		}
+	}
+	result
+}
+
+fn unescape_value(value: &str) -> String {
+	let mut result = String::with_capacity(value.len());
+	let mut chars = value.chars();
+	while let Some(c) = chars.next() {
+		if c != '%' {
+			result.push(c);
+			continue;
+		}
+		let code: String = chars.by_ref().take(2).collect();
+		match u8::from_str_radix(&code, 16) {
+			Ok(byte) => result.push(byte as char),
+			Err(_) => {
+				result.push('%');
+				result.push_str(&code);
+			}
+		}
+	}
+	result
+}
+
+/// Parse a timestamp in the current format, or in the legacy UTC one.
+/// Legacy timestamps are converted to local time.
+pub fn parse_time(time: &str) -> chrono::ParseResult<DateTime<FixedOffset>> {
+	DateTime::parse_from_str(time, DATE_FORMAT).or_else(|err| {
+		let legacy = Utc.datetime_from_str(time, LEGACY_DATE_FORMAT);
+		match legacy {
+			Ok(time) => Ok(local_time(time.timestamp())),
+			// report the error of the format the timestamp looks like
+			Err(legacy_err) if time.contains('_') => Err(legacy_err),
+			Err(_) => Err(err),
+		}
+	})
+}
+
+fn serialize_time<S: Serializer>(
+	time: &DateTime<FixedOffset>,
+	serializer: S,
+) -> Result<S::Ok, S::Error> {
+	serializer.serialize_str(&time.format(DATE_FORMAT).to_string())
+}
+
+fn deserialize_time<'de, D: Deserializer<'de>>(
+	deserializer: D,
+) -> Result<DateTime<FixedOffset>, D::Error> {
+	let time = String::deserialize(deserializer)?;
+	parse_time(&time).map_err(serde::de::Error::custom)
+}
+
+/// The header line that starts a structured log.
+pub fn log_header() -> String {
+	serde_json::to_string(&LogHeader {
+		timeplot_log: LOG_FORMAT_VERSION,
+	})
+	.unwrap()
+}
+
+/// Whether the line is a structured log header rather than an entry.
+pub fn is_header(line: &str) -> bool {
+	line.starts_with('{') && serde_json::from_str::<LogHeader>(line).is_ok()
+}
+
+/// A log line that could not be parsed.
+#[derive(Debug)]
+pub struct ParseError {
+	/// 1-based, or 0 if unknown
+	pub line_number: usize,
+	pub line: String,
+	pub reason: String,
+}
+
+impl std::fmt::Display for ParseError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		if self.line_number > 0 {
+			write!(f, "line {}: ", self.line_number)?;
+		}
+		write!(f, "{}: {}", self.reason, self.line)
+	}
+}
+
+/// Parse a log entry in either the structured or the legacy format.
+/// The error only contains the reason, the caller knows the line and its number.
+pub fn parse_log_line(line: &str) -> Result<LogEntry, String> {
+	let line = line.trim_end_matches(&['\r', '\n'][..]);
+	if log_crypto::is_encrypted(line) {
+		// an encrypted record holds a line of either format
+		let (time, decrypted) = log_crypto::decrypt_line(line)?;
+		let entry = parse_plain_line(&decrypted)?;
+		if parse_time(&time) != Ok(entry.time) {
+			return Err(format!(
+				"record of {} doesn't match its time {}",
+				entry.time, time
+			));
+		}
+		return Ok(entry);
+	}
+	parse_plain_line(line)
+}
+
+fn parse_plain_line(line: &str) -> Result<LogEntry, String> {
+	if line.starts_with('{') {
+		return serde_json::from_str(line).map_err(|err| err.to_string());
+	}
+	let (date, rest) = line
+		.split_once(' ')
+		.ok_or_else(|| "expected a date, a category and a title".to_string())?;
+	let time = parse_time(date).map_err(|err| format!("invalid date '{}', {}", date, err))?;
+	// a legacy title like "duration=abc" is just a title
+	let has_attributes = DateTime::parse_from_str(date, DATE_FORMAT).is_ok();
+	let (category, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+	if category.is_empty() {
+		return Err("missing category".to_string());
+	}
+	let mut entry = LogEntry {
+		time,
+		category: category.to_string(),
+		..Default::default()
+	};
+	while let Some((key, value, remainder)) = split_attribute(rest).filter(|_| has_attributes) {
+		let invalid = |err: &dyn std::fmt::Display| format!("invalid {} '{}', {}", key, value, err);
+		match key {
+			"kind" => entry.kind = value.parse().map_err(|err| invalid(&err))?,
+			"duration" => entry.duration = Some(value.parse().map_err(|err| invalid(&err))?),
+			"project" => entry.project = Some(unescape_value(value)),
+			"host" => entry.host = Some(unescape_value(value)),
+			"manual" => entry.manual = value.parse().map_err(|err| invalid(&err))?,
+			"events" => entry.input_events = Some(value.parse().map_err(|err| invalid(&err))?),
+			"titles" => entry.titles = parse_titles(value).map_err(|err| invalid(&err))?,
+			_ => unreachable!(),
+		}
+		rest = remainder;
+	}
+	let (token, title) = rest.split_once(' ').unwrap_or((rest, ""));
+	entry.title = if has_attributes && token == END_OF_ATTRIBUTES {
+		title
+	} else {
+		rest
+	}
+	.to_string();
+	Ok(entry)
+}
+
+/// Parse `seconds:title,seconds:title` where titles are escaped.
+fn parse_titles(value: &str) -> Result<Vec<TitleDuration>, String> {
+	value
+		.split(',')
+		.map(|item| {
+			let (seconds, title) = item
+				.split_once(':')
+				.ok_or_else(|| "expected seconds:title".to_string())?;
+			Ok(TitleDuration {
+				seconds: seconds.parse().map_err(|err| format!("{}", err))?,
+				title: unescape_value(title),
+			})
+		})
+		.collect()
+}
+
+/// Split a leading `key=value` attribute off the line, if there is a known one.
+fn split_attribute(rest: &str) -> Option<(&str, &str, &str)> {
+	let (token, remainder) = rest.split_once(' ').unwrap_or((rest, ""));
+	let (key, value) = token.split_once('=')?;
+	if ATTRIBUTE_KEYS.contains(&key) {
+		Some((key, value, remainder))
+	} else {
+		None
+	}
+}
+
+/// Format an entry as a line of the structured log.
+pub fn format_json_line(entry: &LogEntry) -> String {
+	serde_json::to_string(entry).expect("failed to serialize log entry")
+}
+
+/// Format an entry as a line of the legacy space-separated log.
+pub fn format_log_line(entry: &LogEntry) -> String {
+	let mut result = format!("{} {}", entry.time.format(DATE_FORMAT), entry.category);
+	if entry.kind != EntryKind::Activity {
+		result.push_str(&format!(" kind={}", entry.kind.name()));
+	}
+	if let Some(duration) = entry.duration {
+		result.push_str(&format!(" duration={}", duration));
+	}
+	if let Some(project) = &entry.project {
+		result.push_str(&format!(" project={}", escape_value(project)));
+	}
+	if let Some(host) = &entry.host {
+		result.push_str(&format!(" host={}", escape_value(host)));
+	}
+	if entry.manual {
+		result.push_str(" manual=true");
+	}
+	if let Some(events) = entry.input_events {
+		result.push_str(&format!(" events={}", events));
+	}
+	if !entry.titles.is_empty() {
+		let titles: Vec<_> = entry
+			.titles
+			.iter()
+			.map(|t| format!("{}:{}", t.seconds, escape_value(&t.title)))
+			.collect();
+		result.push_str(&format!(" titles={}", titles.join(",")));
+	}
+	result.push(' ');
+	let (first_word, _) = entry.title.split_once(' ').unwrap_or((&entry.title, ""));
+	if first_word == END_OF_ATTRIBUTES || split_attribute(&entry.title).is_some() {
+		result.push_str(END_OF_ATTRIBUTES);
+		result.push(' ');
+	}
+	result.push_str(&entry.title);
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn legacy_title_looking_like_attribute() {
+		let entry = parse_log_line("2018-10-01_17:03 work duration=abc project=x").unwrap();
+		assert_eq!(entry.category, "work");
+		assert_eq!(entry.duration, None);
+		assert_eq!(entry.project, None);
+		assert_eq!(entry.title, "duration=abc project=x");
+	}
+
+	#[test]
+	fn attributes_after_current_timestamp() {
+		let entry =
+			parse_log_line("2018-10-01T17:03:27+02:00 work duration=60 project=x Editor").unwrap();
+		assert_eq!(entry.duration, Some(60));
+		assert_eq!(entry.project.as_deref(), Some("x"));
+		assert_eq!(entry.title, "Editor");
+	}
+
+	#[test]
+	fn titles_looking_like_attributes_round_trip() {
+		for title in [
+			"kind=x",
+			"host=build box",
+			"duration=5 min",
+			"-- x",
+			"--",
+			"",
+			"a=b",
+		] {
+			let entry = LogEntry {
+				time: parse_time("2018-10-01T17:03:27+02:00").unwrap(),
+				duration: Some(60),
+				category: "work".to_string(),
+				title: title.to_string(),
+				..Default::default()
+			};
+			let parsed = parse_log_line(&format_log_line(&entry)).unwrap();
+			assert_eq!(parsed.title, title);
+			assert_eq!(parsed.duration, Some(60));
+			assert_eq!(parsed.kind, EntryKind::Activity);
+			assert_eq!(parsed.host, None);
+		}
+	}
+
+	#[test]
+	fn entries_with_changed_titles_are_not_new() {
+		let existing = vec![parse_log_line("2018-10-01T17:03:27+02:00 work hmac:0123").unwrap()];
+		let mut entries = vec![
+			parse_log_line("2018-10-01T17:03:27+02:00 work Editor").unwrap(),
+			parse_log_line("2018-10-01T17:03:27+02:00 work host=laptop Editor").unwrap(),
+			parse_log_line("2018-10-01T17:03:27+02:00 work project=x Editor").unwrap(),
+			// different records of the same second are both new
+			parse_log_line("2018-10-01T17:04:27+02:00 work Editor").unwrap(),
+			parse_log_line("2018-10-01T17:04:27+02:00 work Terminal").unwrap(),
+		];
+		retain_new(&mut entries, &existing);
+		let titles: Vec<_> = entries.iter().map(|e| e.title.as_str()).collect();
+		assert_eq!(titles, vec!["Editor", "Editor", "Editor", "Terminal"]);
+		assert_eq!(entries[0].host.as_deref(), Some("laptop"));
+		assert_eq!(entries[1].project.as_deref(), Some("x"));
+	}
+}
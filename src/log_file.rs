@@ -0,0 +1,294 @@
+use crate::log_crypto;
+use crate::log_entry;
+use crate::log_entry::LogEntry;
+use crate::log_entry::ParseError;
+use crate::timeplot_constants::DATE_FORMAT;
+use crate::timeplot_constants::LOG_FILE_NAME;
+use log::info;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LogFormat {
+	/// `DATE category [key=value...] title`, without a header
+	Legacy,
+	/// A header line followed by one JSON object per line
+	Structured,
+}
+
+pub fn log_path(image_dir: &Path) -> PathBuf {
+	image_dir.join(LOG_FILE_NAME)
+}
+
+/// Format of an existing log file, judging by its first line.
+/// Missing or empty files are `None`.
+pub fn detect_format(file_path: &Path) -> Option<LogFormat> {
+	let file = File::open(file_path).ok()?;
+	let mut first_line = Vec::new();
+	BufReader::new(file)
+		.read_until(b'\n', &mut first_line)
+		.ok()?;
+	let first_line = String::from_utf8_lossy(&first_line);
+	if first_line.trim().is_empty() {
+		None
+	} else if log_entry::is_header(first_line.trim_end()) {
+		Some(LogFormat::Structured)
+	} else {
+		Some(LogFormat::Legacy)
+	}
+}
+
+/// Append an entry in the format the log is already in.
+/// New logs are started in the structured format. The caller holds the `LogLock`.
+pub fn append_entry(image_dir: &Path, entry: &LogEntry) {
+	let file_path = log_path(image_dir);
+	let format = detect_format(&file_path);
+	let mut file = OpenOptions::new()
+		.append(true)
+		.create(true)
+		.open(&file_path)
+		.unwrap_or_else(|err| panic!("failed to open log file {:?}, {}", file_path, err));
+	let log_line = match format {
+		Some(LogFormat::Legacy) => log_entry::format_log_line(entry),
+		Some(LogFormat::Structured) | None => log_entry::format_json_line(entry),
+	};
+	// without the title, which is what encryption and retention keep out of sight
+	info!(
+		"logging: {} {}",
+		entry.time.format(DATE_FORMAT),
+		entry.category
+	);
+	let log_line = match format {
+		Some(_) => log_crypto::encrypt_line(&log_line, &entry.time),
+		None => format!(
+			"{}\n{}",
+			log_entry::log_header(),
+			log_crypto::encrypt_line(&log_line, &entry.time)
+		),
+	};
+	// a single write, so that readers never see half a line
+	file.write_all(format!("{}\n", log_line).as_bytes())
+		.unwrap_or_else(|err| panic!("Failed to write to log file {:?}, {}", file_path, err));
+}
+
+/// Parsed content of a log file. Lines that couldn't be parsed are skipped and reported.
+#[derive(Default)]
+pub struct LogContents {
+	pub entries: Vec<LogEntry>,
+	pub errors: Vec<ParseError>,
+}
+
+/// Next line of the log without the line break, or `None` at the end.
+/// Lines that aren't valid UTF-8 are returned with replacement characters, and `false`.
+fn read_log_line(reader: &mut impl BufRead) -> io::Result<Option<(String, bool)>> {
+	let mut bytes = Vec::new();
+	if reader.read_until(b'\n', &mut bytes)? == 0 {
+		return Ok(None);
+	}
+	while bytes.last() == Some(&b'\n') || bytes.last() == Some(&b'\r') {
+		bytes.pop();
+	}
+	Ok(Some(match String::from_utf8(bytes) {
+		Ok(line) => (line, true),
+		Err(err) => (String::from_utf8_lossy(err.as_bytes()).to_string(), false),
+	}))
+}
+
+/// All entries of a log file, in either format.
+pub fn read_entries(file_path: &Path) -> LogContents {
+	let file = File::open(file_path)
+		.unwrap_or_else(|err| panic!("Failed to open log file {:?}, {}", file_path, err));
+	let mut contents = LogContents::default();
+	parse_lines(BufReader::new(file), &mut contents, true)
+		.unwrap_or_else(|err| panic!("Failed to read log file {:?}, {}", file_path, err));
+	contents
+}
+
+/// Parse all lines of a log (or a part of it) into `contents`.
+/// Line numbers are only reported if the reader is at the start of the file.
+pub fn parse_lines(
+	mut reader: impl BufRead,
+	contents: &mut LogContents,
+	from_start: bool,
+) -> io::Result<()> {
+	let mut line_number = 0;
+	while let Some((line, valid)) = read_log_line(&mut reader)? {
+		line_number += 1;
+		if line.trim().is_empty() || log_entry::is_header(&line) {
+			continue;
+		}
+		let entry = if valid {
+			log_entry::parse_log_line(&line)
+		} else {
+			Err("invalid UTF-8".to_string())
+		};
+		match entry {
+			Ok(entry) => contents.entries.push(entry),
+			Err(reason) => contents.errors.push(ParseError {
+				line_number: if from_start { line_number } else { 0 },
+				line,
+				reason,
+			}),
+		}
+	}
+	Ok(())
+}
+
+/// Time of the first parsable line that starts after `pos`, or `None` at the end of file.
+fn time_after(reader: &mut BufReader<File>, pos: u64) -> io::Result<Option<u64>> {
+	reader.seek(SeekFrom::Start(pos))?;
+	if pos > 0 {
+		// most likely in the middle of a line, skip to the next one
+		reader.read_until(b'\n', &mut Vec::new())?;
+	}
+	while let Some((line, valid)) = read_log_line(reader)? {
+		if let Some(entry) = valid
+			.then(|| log_entry::parse_log_line(&line).ok())
+			.flatten()
+		{
+			return Ok(Some(entry.epoch_seconds()));
+		}
+	}
+	Ok(None)
+}
+
+/// Open the log positioned at a line start shortly before the first entry at `since`.
+///
+/// The log is expected to be sorted by time (see `timeplot log repair`),
+/// so this is a binary search over byte offsets and doesn't get slower as the log grows.
+/// A few older entries may come first, callers filter by time themselves.
+pub fn open_since(file_path: &Path, since: u64) -> io::Result<BufReader<File>> {
+	let mut reader = BufReader::new(File::open(file_path)?);
+	let mut low = 0;
+	let mut high = reader.get_ref().metadata()?.len();
+	// Invariant: the first entry after `low` is older than `since` (or `low` is the start).
+	while high - low > 4096 {
+		let middle = low + (high - low) / 2;
+		match time_after(&mut reader, middle)? {
+			Some(time) if time < since => low = middle,
+			_ => high = middle,
+		}
+	}
+	reader.seek(SeekFrom::Start(low))?;
+	if low > 0 {
+		reader.read_until(b'\n', &mut Vec::new())?;
+	}
+	Ok(reader)
+}
+
+/// Replace the log file with the given entries.
+/// The new content is written to a temporary file first, so that the log is never half-written.
+pub fn rewrite_entries(file_path: &Path, entries: &[LogEntry], format: LogFormat) {
+	let temp_path = file_path.with_extension("tmp");
+	{
+		let file = File::create(&temp_path)
+			.unwrap_or_else(|err| panic!("Failed to create {:?}, {}", temp_path, err));
+		let mut file = BufWriter::new(file);
+		let write_error = |err| panic!("Failed to write to {:?}, {}", temp_path, err);
+		if format == LogFormat::Structured {
+			writeln!(file, "{}", log_entry::log_header()).unwrap_or_else(write_error);
+		}
+		for entry in entries {
+			let line = match format {
+				LogFormat::Legacy => log_entry::format_log_line(entry),
+				LogFormat::Structured => log_entry::format_json_line(entry),
+			};
+			let line = log_crypto::encrypt_line(&line, &entry.time);
+			writeln!(file, "{}", line).unwrap_or_else(write_error);
+		}
+		file.flush().unwrap_or_else(write_error);
+		file.get_ref().sync_all().unwrap_or_else(write_error);
+	}
+	fs::rename(&temp_path, file_path)
+		.unwrap_or_else(|err| panic!("Failed to move {:?} to {:?}, {}", temp_path, file_path, err));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn invalid_utf8_line_is_skipped_when_reading_since() {
+		let file_path =
+			std::env::temp_dir().join(format!("timeplot-{}-since.log", std::process::id()));
+		let mut content = Vec::new();
+		for minute in 0..200 {
+			let time = format!(
+				"2018-10-01T{:02}:{:02}:00+02:00",
+				14 + minute / 60,
+				minute % 60
+			);
+			content.extend(format!("{} work Editor\n", time).bytes());
+			if minute == 150 {
+				content.extend(b"2018-10-01T16:30:30+02:00 work \xff\n");
+			}
+		}
+		fs::write(&file_path, content).unwrap();
+		let since = log_entry::parse_time("2018-10-01T16:35:00+02:00").unwrap();
+		let mut contents = LogContents::default();
+		let reader = open_since(&file_path, since.timestamp() as u64).unwrap();
+		parse_lines(reader, &mut contents, false).unwrap();
+		fs::remove_file(&file_path).unwrap();
+		let recent = contents.entries.iter().filter(|e| e.time >= since);
+		assert_eq!(recent.count(), 45);
+		assert!(contents.errors.iter().all(|e| e.line_number == 0));
+	}
+
+	#[test]
+	fn invalid_utf8_line_is_reported() {
+		let file_path =
+			std::env::temp_dir().join(format!("timeplot-{}-utf8.log", std::process::id()));
+		let mut content = b"2018-10-01T17:03:27+02:00 work Editor\n".to_vec();
+		content.extend(b"2018-10-01T17:04:27+02:00 work \xff\xfe\n");
+		content.extend(b"2018-10-01T17:05:27+02:00 work Terminal\n");
+		fs::write(&file_path, content).unwrap();
+		let contents = read_entries(&file_path);
+		fs::remove_file(&file_path).unwrap();
+		assert_eq!(contents.entries.len(), 2);
+		assert_eq!(contents.errors.len(), 1);
+		assert_eq!(contents.errors[0].line_number, 2);
+	}
+
+	#[test]
+	fn encrypted_legacy_log_round_trip() {
+		let image_dir =
+			std::env::temp_dir().join(format!("timeplot-{}-encrypted", std::process::id()));
+		fs::create_dir_all(&image_dir).unwrap();
+		let keyfile = image_dir.join("log.key");
+		fs::write(&keyfile, "test key").unwrap();
+		let conf = config::Config::builder()
+			.set_override("encryption.keyfile", keyfile.to_str().unwrap())
+			.unwrap()
+			.build()
+			.unwrap();
+		log_crypto::init(&conf, &image_dir);
+		fs::write(
+			log_path(&image_dir),
+			"2018-10-01T17:03:27+02:00 work Editor\n",
+		)
+		.unwrap();
+		let entry =
+			log_entry::parse_log_line("2018-10-01T17:04:27+02:00 work duration=60 Terminal")
+				.unwrap();
+		append_entry(&image_dir, &entry);
+		let content = fs::read_to_string(log_path(&image_dir)).unwrap();
+		let contents = read_entries(&log_path(&image_dir));
+		fs::remove_dir_all(&image_dir).unwrap();
+		assert!(log_crypto::is_encrypted(content.lines().last().unwrap()));
+		assert!(!content.contains("Terminal"));
+		assert!(contents.errors.is_empty());
+		assert_eq!(contents.entries.len(), 2);
+		let read = &contents.entries[1];
+		assert_eq!(read.time, entry.time);
+		assert_eq!(read.duration, Some(60));
+		assert_eq!(read.title, "Terminal");
+	}
+}
@@ -0,0 +1,42 @@
+use crate::timeplot_constants::LOCK_FILE_NAME;
+use fs2::FileExt;
+use log::info;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::path::Path;
+
+/// Advisory lock on the log, so that the daemon and one-off commands don't change it at once.
+/// Everything that writes to the log or its database holds it: the daemon while saving,
+/// and commands like `amend`, `add`, `import` or `log repair` for their whole run.
+/// Readers don't need it, since files are only appended to or replaced by renaming a complete one.
+/// Released when dropped.
+///
+/// It's a separate file rather than the log itself, because rewriting the log replaces the file.
+pub struct LogLock {
+	file: File,
+}
+
+impl LogLock {
+	/// Wait until no other process changes the log, then lock it.
+	pub fn acquire(image_dir: &Path) -> LogLock {
+		let file_path = image_dir.join(LOCK_FILE_NAME);
+		let file = OpenOptions::new()
+			.create(true)
+			.truncate(false)
+			.write(true)
+			.open(&file_path)
+			.unwrap_or_else(|err| panic!("Failed to open lock file {:?}, {}", file_path, err));
+		if FileExt::try_lock_exclusive(&file).is_err() {
+			info!("Waiting for another process to finish changing the log");
+			FileExt::lock_exclusive(&file)
+				.unwrap_or_else(|err| panic!("Failed to lock {:?}, {}", file_path, err));
+		}
+		LogLock { file }
+	}
+}
+
+impl Drop for LogLock {
+	fn drop(&mut self) {
+		let _ = FileExt::unlock(&self.file);
+	}
+}
@@ -0,0 +1,210 @@
+use crate::log_entry;
+use crate::log_entry::EntryKind;
+use crate::log_entry::LogEntry;
+use crate::log_file::LogContents;
+use crate::log_rotation;
+use crate::storage::Storage;
+use crate::timeplot_constants::CONFIG_PARSE_ERROR;
+use config::Config;
+use log::info;
+use log::warn;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// What to do with merged activity that overlaps activity recorded on this machine.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OverlapPolicy {
+	/// Keep both, e.g. when both machines are really used at the same time
+	Keep,
+	/// Drop the merged entry, this machine's record wins
+	Local,
+}
+
+fn overlap_policy(conf: &Config) -> OverlapPolicy {
+	match conf.get_string("merge.overlap") {
+		Ok(policy) if policy == "keep" => OverlapPolicy::Keep,
+		Ok(policy) if policy == "local" => OverlapPolicy::Local,
+		Ok(policy) => {
+			warn!("Unknown merge.overlap value {}, using local", policy);
+			OverlapPolicy::Local
+		}
+		Err(_) => OverlapPolicy::Local,
+	}
+}
+
+/// Name of this machine, as used in the `host` of merged entries.
+pub fn local_hostname() -> String {
+	hostname::get()
+		.map(|name| name.to_string_lossy().to_string())
+		.unwrap_or_else(|err| panic!("Failed to get hostname, {}", err))
+}
+
+/// Host of a log file judging by its name: `laptop.log` or `laptop/log.log` are from "laptop".
+fn host_of(file_path: &Path) -> Option<String> {
+	let name = file_path.file_name()?.to_str()?;
+	let stem = name.split('.').next()?;
+	if stem == "log" {
+		let parent = file_path.parent()?.file_name()?.to_str()?;
+		Some(parent.to_string())
+	} else {
+		Some(stem.to_string())
+	}
+}
+
+/// Seconds covered by an entry, assuming one iteration for entries without duration.
+fn interval(entry: &LogEntry, default_duration: u64) -> (u64, u64) {
+	let start = entry.epoch_seconds();
+	(start, start + entry.duration.unwrap_or(default_duration))
+}
+
+/// Outcome of merging a log file.
+pub struct Merged {
+	/// Number of added entries
+	pub added: usize,
+	/// Time of the latest entry in the file, whether it was added or not
+	pub latest: Option<u64>,
+}
+
+/// Merge the entries of a log of another machine after `after` into `storage`.
+/// Entries are tagged with `host` (by default judging by the file name),
+/// and ones that are already there are skipped, so merging the same file again does nothing.
+pub fn merge_file(
+	storage: &mut dyn Storage,
+	conf: &Config,
+	file_path: &Path,
+	host: Option<&str>,
+	after: u64,
+) -> Result<Merged, String> {
+	let host = match host {
+		Some(host) => host.to_string(),
+		None => host_of(file_path)
+			.ok_or_else(|| format!("can't tell the host of {:?}, specify it", file_path))?,
+	};
+	let local_host = local_hostname();
+	if host == local_host {
+		return Err(format!(
+			"{:?} is a log of this machine, {}",
+			file_path, host
+		));
+	}
+	let mut incoming = LogContents::default();
+	log_rotation::read_archive(file_path, &mut incoming)
+		.map_err(|err| format!("failed to read {:?}, {}", file_path, err))?;
+	if !incoming.errors.is_empty() {
+		warn!(
+			"Skipping {} lines of {:?} that could not be parsed",
+			incoming.errors.len(),
+			file_path
+		);
+	}
+	let latest = incoming.entries.iter().map(LogEntry::epoch_seconds).max();
+	let mut incoming: Vec<LogEntry> = incoming
+		.entries
+		.into_iter()
+		.filter(|entry| entry.epoch_seconds() > after)
+		.map(|mut entry| {
+			entry.host.get_or_insert_with(|| host.clone());
+			entry
+		})
+		// this machine's entries that came back through the other machine's log
+		.filter(|entry| entry.host.as_deref() != Some(local_host.as_str()))
+		.collect();
+	incoming.sort_by_key(|entry| entry.time);
+	let since = match incoming.first() {
+		Some(entry) => entry.epoch_seconds(),
+		None => return Ok(Merged { added: 0, latest }),
+	};
+	let existing = storage.entries_since(since)?.entries;
+	log_entry::retain_new(&mut incoming, &existing);
+
+	if overlap_policy(conf) == OverlapPolicy::Local {
+		let default_duration = (conf
+			.get_float("main.sleep_minutes")
+			.expect(CONFIG_PARSE_ERROR)
+			* 60.0) as u64;
+		// intervals of this machine's activity, sorted by start, with the latest end so far
+		let mut local: Vec<(u64, u64)> = existing
+			.iter()
+			.filter(|entry| entry.host.is_none() && entry.kind == EntryKind::Activity)
+			.map(|entry| interval(entry, default_duration))
+			.collect();
+		local.sort_unstable();
+		let mut latest_end = 0;
+		for (_, end) in local.iter_mut() {
+			latest_end = latest_end.max(*end);
+			*end = latest_end;
+		}
+		incoming.retain(|entry| {
+			if entry.kind != EntryKind::Activity {
+				return true;
+			}
+			let (start, end) = interval(entry, default_duration);
+			let before_end = local.partition_point(|(local_start, _)| *local_start < end);
+			before_end == 0 || local[before_end - 1].1 <= start
+		});
+	}
+	if !incoming.is_empty() {
+		storage.insert(&incoming)?;
+	}
+	Ok(Merged {
+		added: incoming.len(),
+		latest,
+	})
+}
+
+/// A directory that other machines' logs are synced to, e.g. with Syncthing (`merge.inbox`).
+/// Files are merged when they appear or change, and only entries newer than the ones
+/// merged before are looked at.
+#[derive(Default)]
+pub struct Inbox {
+	/// Modification time of each file when it was last merged, and its latest entry then
+	merged: HashMap<PathBuf, (SystemTime, u64)>,
+}
+
+impl Inbox {
+	pub fn merge_changed(&mut self, storage: &mut dyn Storage, conf: &Config) {
+		let dir = match conf.get_string("merge.inbox") {
+			Ok(dir) => PathBuf::from(dir),
+			Err(_) => return,
+		};
+		let files = match fs::read_dir(&dir) {
+			Ok(files) => files,
+			Err(err) => {
+				warn!("Failed to read merge.inbox {:?}, {}", dir, err);
+				return;
+			}
+		};
+		for file in files.filter_map(|file| file.ok()) {
+			let name = file.file_name().to_string_lossy().to_string();
+			// hidden and temporary files of sync tools
+			if name.starts_with('.') || name.ends_with('~') || name.ends_with(".tmp") {
+				continue;
+			}
+			let modified = match file.metadata().and_then(|m| m.modified()) {
+				Ok(modified) if file.path().is_file() => modified,
+				_ => continue,
+			};
+			let path = file.path();
+			let after = match self.merged.get(&path) {
+				Some((merged, _)) if *merged == modified => continue,
+				Some((_, latest)) => *latest,
+				None => 0,
+			};
+			let latest = match merge_file(storage, conf, &path, None, after) {
+				Ok(merged) => {
+					info!("Merged {} entries from {:?}", merged.added, path);
+					merged.latest.unwrap_or(after)
+				}
+				Err(err) => {
+					warn!("Failed to merge {:?}, {}", path, err);
+					after
+				}
+			};
+			// not retried until it changes, whether it worked or not
+			self.merged.insert(path, (modified, latest));
+		}
+	}
+}
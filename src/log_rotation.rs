@@ -0,0 +1,327 @@
+use crate::file_operations;
+use crate::log_crypto;
+use crate::log_entry;
+use crate::log_entry::LogEntry;
+use crate::log_file;
+use crate::log_file::LogContents;
+use config::Config;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use log::info;
+use log::warn;
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
+use std::path::PathBuf;
+
+const MONTH_FORMAT: &str = "%Y-%m";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compression {
+	None,
+	Gzip,
+	Zstd,
+}
+
+impl Compression {
+	fn extension(self) -> &'static str {
+		match self {
+			Compression::None => "",
+			Compression::Gzip => ".gz",
+			Compression::Zstd => ".zst",
+		}
+	}
+}
+
+/// How to rotate, from `main.rotate_monthly` and `main.compress_archives`.
+/// `None` if the log isn't rotated.
+pub fn configured_compression(conf: &Config) -> Option<Compression> {
+	if !conf.get_bool("main.rotate_monthly").unwrap_or(false) {
+		return None;
+	}
+	match conf.get_string("main.compress_archives") {
+		Ok(compression) if compression == "gzip" => Some(Compression::Gzip),
+		Ok(compression) if compression == "zstd" => Some(Compression::Zstd),
+		Ok(compression) if compression == "none" => Some(Compression::None),
+		Ok(compression) => {
+			warn!(
+				"Unknown main.compress_archives value {}, not compressing",
+				compression
+			);
+			Some(Compression::None)
+		}
+		Err(_) => Some(Compression::None),
+	}
+}
+
+/// First free archive name for the month, e.g. `log.2018-10.log` or `log.2018-10.2.log`.
+fn archive_path(image_dir: &Path, month: &str, compression: Compression) -> PathBuf {
+	let taken = |name: &str| {
+		[Compression::None, Compression::Gzip, Compression::Zstd]
+			.iter()
+			.any(|c| {
+				image_dir
+					.join(format!("{}{}", name, c.extension()))
+					.exists()
+			})
+	};
+	let mut number = 1;
+	loop {
+		let name = if number == 1 {
+			format!("log.{}.log", month)
+		} else {
+			format!("log.{}.{}.log", month, number)
+		};
+		if !taken(&name) {
+			return image_dir.join(format!("{}{}", name, compression.extension()));
+		}
+		number += 1;
+	}
+}
+
+/// Move log.log to an archive if `entry` is from a different month than the last logged one.
+/// A log spanning several months, e.g. on the first rotation, gets one archive per month.
+pub fn rotate_if_new_month(image_dir: &Path, entry: &LogEntry, compression: Compression) {
+	let file_path = log_file::log_path(image_dir);
+	let last_entry = match file_operations::read_last_line(&file_path) {
+		Some(line) => match log_entry::parse_log_line(&line) {
+			Ok(entry) => entry,
+			Err(_) => return,
+		},
+		None => return,
+	};
+	let month = month_of(&last_entry);
+	if month_of(entry) == month {
+		return;
+	}
+	let contents = log_file::read_entries(&file_path);
+	let mut months: BTreeMap<String, Vec<LogEntry>> = BTreeMap::new();
+	for entry in contents.entries {
+		months.entry(month_of(&entry)).or_default().push(entry);
+	}
+	if months.len() > 1 && contents.errors.is_empty() {
+		for (month, entries) in months {
+			let archive = archive_path(image_dir, &month, compression);
+			info!("Moving log of {} to {:?}", month, archive);
+			write_archive(&archive, &entries);
+		}
+		fs::remove_file(&file_path)
+			.unwrap_or_else(|err| panic!("Failed to remove {:?}, {}", file_path, err));
+		return;
+	}
+	if months.len() > 1 {
+		// unreadable lines can't be assigned to a month, keep them together with the rest
+		warn!(
+			"{:?} has unreadable lines, archiving it whole under {}",
+			file_path, month
+		);
+	}
+	let archive = archive_path(image_dir, &month, compression);
+	info!("Moving log of {} to {:?}", month, archive);
+	let result = match compression {
+		Compression::None => fs::rename(&file_path, &archive),
+		Compression::Gzip | Compression::Zstd => File::open(&file_path)
+			.and_then(|input| write_compressed(input, &archive, compression))
+			.and_then(|_| fs::remove_file(&file_path)),
+	};
+	result.unwrap_or_else(|err| panic!("Failed to move {:?} to {:?}, {}", file_path, archive, err));
+}
+
+/// Write `input` to `target` through a temporary file, compressing it as asked.
+fn write_compressed(
+	mut input: impl Read,
+	target: &Path,
+	compression: Compression,
+) -> io::Result<()> {
+	let temp_path = target.with_extension("tmp");
+	let mut output = File::create(&temp_path)?;
+	let output = match compression {
+		Compression::None => {
+			io::copy(&mut input, &mut output)?;
+			output
+		}
+		Compression::Gzip => {
+			let mut encoder = GzEncoder::new(output, flate2::Compression::default());
+			io::copy(&mut input, &mut encoder)?;
+			encoder.finish()?
+		}
+		Compression::Zstd => {
+			let mut encoder = zstd::Encoder::new(output, 0)?;
+			io::copy(&mut input, &mut encoder)?;
+			encoder.finish()?
+		}
+	};
+	output.sync_all()?;
+	fs::rename(&temp_path, target)
+}
+
+/// Month and sequence number of an archive, judging by its name.
+fn archive_month(file_name: &str) -> Option<(&str, u32)> {
+	let rest = file_name.strip_prefix("log.")?;
+	let month = rest.get(..7)?;
+	if month.as_bytes()[4] != b'-' || !month.replace('-', "").bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	}
+	let rest = rest[7..]
+		.trim_end_matches(".gz")
+		.trim_end_matches(".zst")
+		.strip_suffix(".log")?;
+	if rest.is_empty() {
+		Some((month, 1))
+	} else {
+		Some((month, rest.strip_prefix('.')?.parse().ok()?))
+	}
+}
+
+/// All archives with their month and sequence number, oldest first.
+fn archives(image_dir: &Path) -> Vec<(String, u32, PathBuf)> {
+	let dir = match fs::read_dir(image_dir) {
+		Ok(dir) => dir,
+		Err(_) => return Vec::new(),
+	};
+	let mut result: Vec<(String, u32, PathBuf)> = dir
+		.filter_map(|file| file.ok())
+		.filter_map(|file| {
+			let name = file.file_name().into_string().ok()?;
+			let (month, number) = archive_month(&name)?;
+			Some((month.to_string(), number, file.path()))
+		})
+		.collect();
+	result.sort();
+	result
+}
+
+/// Archives that may contain entries at or after `since`, oldest first.
+pub fn archives_since(image_dir: &Path, since: u64) -> Vec<PathBuf> {
+	// a couple of days earlier, in case the UTC offset changed around the month's start
+	let first_month = log_entry::local_time(since.saturating_sub(2 * 24 * 3600) as i64)
+		.format(MONTH_FORMAT)
+		.to_string();
+	archives(image_dir)
+		.into_iter()
+		.filter(|(month, _, _)| *month >= first_month)
+		.map(|(_, _, path)| path)
+		.collect()
+}
+
+/// Month of an entry, as in archive names.
+pub fn month_of(entry: &LogEntry) -> String {
+	entry.time.format(MONTH_FORMAT).to_string()
+}
+
+/// Add entries of an earlier month to the last archive of that month, or to a new one.
+/// Fails if the archive has lines that could not be parsed, rewriting it would lose them.
+pub fn insert_into_archive(
+	image_dir: &Path,
+	month: &str,
+	entries: &[LogEntry],
+	compression: Compression,
+) -> Result<(), String> {
+	let existing = archives(image_dir)
+		.into_iter()
+		.filter(|(archive_month, _, _)| archive_month == month)
+		.map(|(_, _, path)| path)
+		.next_back();
+	let mut contents = LogContents::default();
+	let file_path = match existing {
+		Some(file_path) => {
+			read_archive(&file_path, &mut contents)
+				.map_err(|err| format!("failed to read log archive {:?}, {}", file_path, err))?;
+			file_path
+		}
+		None => archive_path(image_dir, month, compression),
+	};
+	if !contents.errors.is_empty() {
+		return Err(format!(
+			"{:?} has {} lines that could not be parsed, see `timeplot log repair`",
+			file_path,
+			contents.errors.len()
+		));
+	}
+	contents.entries.extend(entries.iter().cloned());
+	contents.entries.sort_by_key(|entry| entry.time);
+	write_archive(&file_path, &contents.entries);
+	Ok(())
+}
+
+fn compression_of(file_path: &Path) -> Compression {
+	let name = file_path.to_string_lossy();
+	if name.ends_with(".gz") {
+		Compression::Gzip
+	} else if name.ends_with(".zst") {
+		Compression::Zstd
+	} else {
+		Compression::None
+	}
+}
+
+/// Parse an archive, decompressing it if needed.
+pub fn read_archive(file_path: &Path, contents: &mut LogContents) -> io::Result<()> {
+	let file = File::open(file_path)?;
+	match compression_of(file_path) {
+		Compression::Gzip => {
+			log_file::parse_lines(BufReader::new(GzDecoder::new(file)), contents, true)
+		}
+		Compression::Zstd => {
+			log_file::parse_lines(BufReader::new(zstd::Decoder::new(file)?), contents, true)
+		}
+		Compression::None => log_file::parse_lines(BufReader::new(file), contents, true),
+	}
+}
+
+/// Replace the content of an archive, compressed the same way as before.
+pub fn write_archive(file_path: &Path, entries: &[LogEntry]) {
+	let mut content = log_entry::log_header();
+	content.push('\n');
+	for entry in entries {
+		let line = log_entry::format_json_line(entry);
+		content.push_str(&log_crypto::encrypt_line(&line, &entry.time));
+		content.push('\n');
+	}
+	write_compressed(content.as_bytes(), file_path, compression_of(file_path))
+		.unwrap_or_else(|err| panic!("Failed to write {:?}, {}", file_path, err));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rotation_splits_months() {
+		let image_dir =
+			std::env::temp_dir().join(format!("timeplot-{}-rotation", std::process::id()));
+		fs::create_dir_all(&image_dir).unwrap();
+		let lines = [
+			"2018-09-30T23:50:00+02:00 work Editor",
+			"2018-10-01T00:10:00+02:00 work Terminal",
+			"2018-10-31T12:00:00+01:00 work Editor",
+		];
+		fs::write(log_file::log_path(&image_dir), lines.join("\n") + "\n").unwrap();
+		let entry = log_entry::parse_log_line("2018-11-01T09:00:00+01:00 work Editor").unwrap();
+		rotate_if_new_month(&image_dir, &entry, Compression::Gzip);
+		let archives = archives_since(&image_dir, 0);
+		let counts: Vec<usize> = archives
+			.iter()
+			.map(|path| {
+				let mut contents = LogContents::default();
+				read_archive(path, &mut contents).unwrap();
+				contents.entries.len()
+			})
+			.collect();
+		let log_exists = log_file::log_path(&image_dir).exists();
+		fs::remove_dir_all(&image_dir).unwrap();
+		assert_eq!(
+			archives,
+			vec![
+				image_dir.join("log.2018-09.log.gz"),
+				image_dir.join("log.2018-10.log.gz")
+			]
+		);
+		assert_eq!(counts, vec![1, 2]);
+		assert!(!log_exists);
+	}
+}
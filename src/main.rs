@@ -3,14 +3,42 @@ static GLOBAL: std::alloc::System = std::alloc::System;
 
 mod autostart;
 mod command_line_interface;
+#[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
+mod display;
+mod export;
 mod file_operations;
+mod import;
+mod input_activity;
+mod log_commands;
+mod log_crypto;
+mod log_edit;
+mod log_entry;
+mod log_file;
+mod log_lock;
+mod log_merge;
+mod log_rotation;
 mod plotting;
+mod power_events;
+mod project;
+mod retention;
+mod rules;
+mod sampling;
+mod storage;
+mod svg_plot;
 mod timeplot_constants;
 
 use crate::command_line_interface::CLIOptions;
+use crate::command_line_interface::Command as CLICommand;
+use crate::command_line_interface::LogCommand;
+use crate::log_entry::EntryKind;
+use crate::log_entry::LogEntry;
+use crate::log_lock::LogLock;
+use crate::rules::Rules;
+use crate::sampling::Sample;
+use crate::storage::Storage;
 use crate::timeplot_constants::CONFIG_PARSE_ERROR;
-use crate::timeplot_constants::DATE_FORMAT;
-use crate::timeplot_constants::LOG_FILE_NAME;
+use crate::timeplot_constants::MAX_IDLE_SECONDS;
+use crate::timeplot_constants::RULES_FILE_NAME;
 
 use chrono::prelude::*;
 use config::Config;
@@ -24,15 +52,12 @@ use log::warn;
 use std::env;
 use std::fs;
 use std::fs::File;
-use std::fs::OpenOptions;
 use std::io::prelude::*;
-use std::io::BufReader;
-use std::path::Path;
 use std::process::Command;
 use std::time::Duration;
 
+#[cfg(target_os = "windows")]
 const WINDOW_MAX_LENGTH: usize = 200;
-const RULES_FILE_NAME: &str = "rules_simple.txt";
 #[cfg(target_os = "macos")]
 const MAC_SCRIPT_NAME: &str = "get_title.scpt";
 
@@ -49,38 +74,28 @@ fn log_command_failure(child: &std::process::Output) {
 }
 
 fn get_category(activity_info: &WindowActivityInformation, dirs: &ProjectDirs) -> String {
-	let window_name = activity_info.window_name.to_lowercase();
-	let rules_file = dirs.config_dir().join(RULES_FILE_NAME);
-	let rules_file = File::open(&rules_file)
-		.unwrap_or_else(|err| panic!("Failed to open rules file {:?}, {}", rules_file, err));
-	let rules_file = BufReader::new(rules_file);
-
-	for (line_number, line) in rules_file.lines().enumerate() {
-		let line = line
-			.unwrap_or_else(|err| panic!("failed to read rules on line {}, {}", line_number, err));
-		let line = line.trim_start();
-		if line.starts_with('#') || line.is_empty() {
-			continue;
-		}
-		let split: Vec<&str> = line.splitn(2, ' ').collect();
-		let category = split[0];
-		let window_pattern = *split.get(1).unwrap_or(&"");
-		let window_pattern = window_pattern.to_lowercase();
-		if window_name.contains(&window_pattern) {
-			return category.to_string();
+	let rules = Rules::load(dirs.config_dir());
+	match rules.category(&activity_info.window_name) {
+		Some(category) => category.to_string(),
+		None => {
+			warn!(
+				"Could not find any category for: {}",
+				activity_info.window_name.to_lowercase()
+			);
+			"skip".to_string()
 		}
 	}
-	warn!("Could not find any category for: {}", window_name);
-	"skip".to_string()
 }
 
-struct WindowActivityInformation {
+pub struct WindowActivityInformation {
 	window_name: String,
 	idle_seconds: u32,
+	/// Process owning the window, if known
+	pid: Option<u32>,
 }
 
 #[cfg(target_os = "macos")]
-fn get_window_activity_info(dirs: &ProjectDirs) -> WindowActivityInformation {
+fn get_window_activity_info(dirs: &ProjectDirs, _: &Config) -> WindowActivityInformation {
 	let command = Command::new(dirs.config_dir().join(MAC_SCRIPT_NAME))
 		.output()
 		.expect("window title extraction script failed to launch");
@@ -88,10 +103,11 @@ fn get_window_activity_info(dirs: &ProjectDirs) -> WindowActivityInformation {
 	WindowActivityInformation {
 		window_name: String::from_utf8_lossy(&command.stdout).to_string(),
 		idle_seconds: 0,
+		pid: None,
 	}
 }
 #[cfg(target_os = "windows")]
-fn get_window_activity_info(_: &ProjectDirs) -> WindowActivityInformation {
+fn get_window_activity_info(_: &ProjectDirs, _: &Config) -> WindowActivityInformation {
 	use winapi::um::winuser;
 	let mut vec = Vec::with_capacity(WINDOW_MAX_LENGTH);
 	unsafe {
@@ -106,18 +122,35 @@ fn get_window_activity_info(_: &ProjectDirs) -> WindowActivityInformation {
 	WindowActivityInformation {
 		window_name: String::from_utf16_lossy(&vec),
 		idle_seconds: 0,
+		pid: None,
 	}
 }
 #[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
-fn get_window_activity_info(_: &ProjectDirs) -> WindowActivityInformation {
+fn get_window_activity_info(_: &ProjectDirs, conf: &Config) -> WindowActivityInformation {
+	// with several displays/screens, the least idle one is the one in use
+	let (success, info) = display::displays(conf)
+		.iter()
+		.map(|display| get_display_activity_info(display))
+		.min_by_key(|(success, info)| (!success, info.idle_seconds))
+		.expect("at least one display is always returned");
+	if !success {
+		// the session may have moved to another display
+		display::forget_discovered();
+	}
+	info
+}
+/// Window activity on the given display, and whether the active window could be queried.
+#[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
+fn get_display_activity_info(display: &str) -> (bool, WindowActivityInformation) {
 	let command = Command::new("xdotool")
+		.env("DISPLAY", display)
 		.arg("getactivewindow")
 		.arg("getwindowname")
 		.output()
 		.expect("ERROR: command not found: xdotool");
 	log_command_failure(&command);
 
-	let idle_time = match Command::new("xprintidle").output() {
+	let idle_time = match Command::new("xprintidle").env("DISPLAY", display).output() {
 		Err(err) => {
 			warn!(
 				"Failed to run xprintidle. Assuming window is not idle. Error: {}",
@@ -138,10 +171,20 @@ fn get_window_activity_info(_: &ProjectDirs) -> WindowActivityInformation {
 		}
 	};
 
-	WindowActivityInformation {
+	let pid = Command::new("xdotool")
+		.env("DISPLAY", display)
+		.arg("getactivewindow")
+		.arg("getwindowpid")
+		.output()
+		.ok()
+		.and_then(|output| String::from_utf8_lossy(&output.stdout).trim().parse().ok());
+
+	let info = WindowActivityInformation {
 		window_name: String::from_utf8_lossy(&command.stdout).to_string(),
 		idle_seconds: idle_time,
-	}
+		pid,
+	};
+	(command.status.success(), info)
 }
 
 fn run_category_command(conf: &Config, category: &str, window_name: &str) {
@@ -183,46 +226,66 @@ fn run_category_command(conf: &Config, category: &str, window_name: &str) {
 	}
 }
 
-fn do_save_current(dirs: &ProjectDirs, image_dir: &Path, conf: &Config) {
-	let mut activity_info = get_window_activity_info(dirs);
+/// Observe the currently active window, or `None` if the user is idle.
+fn take_sample(dirs: &ProjectDirs, conf: &Config, time: DateTime<Utc>) -> Option<Sample> {
+	let mut activity_info = get_window_activity_info(dirs, conf);
 	activity_info.window_name = activity_info.window_name.trim().replace('\n', " ");
-	if activity_info.idle_seconds > 60 * 3 {
-		info!(
-			"skipping log due to inactivity time: {}sec, {}",
+	if activity_info.idle_seconds > MAX_IDLE_SECONDS {
+		debug!(
+			"skipping sample due to inactivity time: {}sec, {}",
 			activity_info.idle_seconds, activity_info.window_name
 		);
-		return;
+		return None;
 	}
-	let category = get_category(&activity_info, dirs);
-	run_category_command(conf, &category, &activity_info.window_name);
-
-	let file_path = image_dir.join(LOG_FILE_NAME);
-	let mut file = OpenOptions::new()
-		.append(true)
-		.create(true)
-		.open(&file_path)
-		.unwrap_or_else(|err| panic!("failed to open log file {:?}, {}", file_path, err));
-	let log_line = format!(
-		"{} {} {}",
-		Utc::now().format(DATE_FORMAT),
-		category,
-		activity_info
-			.window_name
-			.chars()
-			.take(WINDOW_MAX_LENGTH)
-			.collect::<String>()
-	);
-	info!("logging: {}", log_line);
-	file.write_all(log_line.as_bytes())
-		.unwrap_or_else(|err| panic!("Failed to write to log file {:?}, {}", file_path, err));
-	file.write_all(b"\n").unwrap_or_else(|err| {
-		panic!(
-			"Failed to write newline to log file {:?}, {}",
-			file_path, err
-		)
+	Some(Sample {
+		time,
+		seconds: 0,
+		category: get_category(&activity_info, dirs),
+		project: project::detect_project(&activity_info, conf),
+		input_events: None,
+		window_name: activity_info.window_name,
+	})
+}
+
+fn do_save_current(storage: &mut dyn Storage, conf: &Config, samples: &[Sample]) {
+	let entry = sampling::aggregate(samples);
+	let entry = match entry {
+		Some(entry) => entry,
+		None => {
+			info!("skipping log due to inactivity");
+			return;
+		}
+	};
+	run_category_command(conf, &entry.category, &entry.title);
+	storage.append(&entry);
+}
+
+/// Record that the computer was offline (suspended or shut down) between the given moments.
+fn save_offline(storage: &mut dyn Storage, since: DateTime<Utc>, until: DateTime<Utc>) {
+	storage.append(&LogEntry {
+		time: log_entry::local_time(since.timestamp()),
+		duration: Some((until - since).num_seconds().max(0) as u64),
+		kind: EntryKind::Offline,
+		category: "offline".to_string(),
+		..Default::default()
 	});
 }
 
+/// If the previous run ended without recording it (e.g. on shutdown), do it now.
+fn save_offline_since_last_run(storage: &mut dyn Storage, flush_seconds: i64) {
+	let last_entry = match storage.last_entry() {
+		Some(entry) => entry,
+		None => return,
+	};
+	let last_duration = last_entry.duration.map_or(flush_seconds, |d| d as i64);
+	let last_end = last_entry.time.with_timezone(&Utc) + chrono::Duration::seconds(last_duration);
+	if last_entry.kind != EntryKind::Offline
+		&& (Utc::now() - last_end).num_seconds() > flush_seconds
+	{
+		save_offline(storage, last_end, Utc::now());
+	}
+}
+
 #[cfg(target_os = "windows")]
 pub fn prepare_scripts(_: &ProjectDirs) {}
 #[cfg(target_os = "macos")]
@@ -273,11 +336,10 @@ fn main() {
 		.unwrap_or_else(|| dirs.data_local_dir().to_path_buf());
 
 	default_env("PATH", "/usr/local/bin:/usr/bin:/bin:/usr/local/sbin");
-	default_env("DISPLAY", ":0.0");
-	default_env(
-		"XAUTHORITY",
-		user_dirs.home_dir().join(".Xauthority").to_str().unwrap(),
-	);
+	#[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
+	if let Some(xauthority) = display::xauthority(user_dirs.home_dir()) {
+		default_env("XAUTHORITY", xauthority.to_str().unwrap());
+	}
 
 	info!("Config dir: {}", dirs.config_dir().to_str().unwrap());
 	fs::create_dir_all(dirs.config_dir()).expect("Failed to create config dir");
@@ -302,6 +364,47 @@ fn main() {
 		.build_cloned()
 		.expect("Failed to read config file");
 
+	log_crypto::init(&conf, &image_dir);
+
+	if let Some(command) = &opt.command {
+		match command {
+			CLICommand::Log(LogCommand::Migrate) => log_commands::migrate(&image_dir),
+			CLICommand::Log(LogCommand::Check) => log_commands::check(&image_dir),
+			CLICommand::Log(LogCommand::Repair { dry_run }) => {
+				log_commands::repair(&image_dir, dirs.config_dir(), &conf, *dry_run)
+			}
+			CLICommand::Log(LogCommand::ToSqlite) => log_commands::to_sqlite(&image_dir, &conf),
+			CLICommand::Log(LogCommand::ToText) => log_commands::to_text(&image_dir),
+			CLICommand::Log(LogCommand::Encrypt) => log_commands::encrypt(&image_dir, &conf),
+			CLICommand::Log(LogCommand::Merge { files, host }) => {
+				log_commands::merge(&image_dir, &conf, files, host.as_deref())
+			}
+			CLICommand::Add {
+				category,
+				from,
+				to,
+				note,
+			} => log_edit::add(
+				&image_dir,
+				dirs.config_dir(),
+				&conf,
+				category,
+				*from,
+				*to,
+				note.as_deref(),
+			),
+			CLICommand::Mark { text, at } => log_edit::mark(&image_dir, &conf, text, *at),
+			CLICommand::Amend(options) => {
+				log_edit::amend(&image_dir, dirs.config_dir(), &conf, options)
+			}
+			CLICommand::Export(options) => export::run(&image_dir, &conf, options),
+			CLICommand::Import(options) => {
+				import::run(&image_dir, dirs.config_dir(), &conf, options)
+			}
+		}
+		return;
+	}
+
 	if conf
 		.get_bool("beginner.create_autostart_entry")
 		.unwrap_or(false)
@@ -328,16 +431,89 @@ fn main() {
 		std::process::exit(1)
 	}
 
+	let mut samples = Vec::new();
+	// the latest sample, which lasts until the next one is taken
+	let mut open_sample: Option<Sample> = None;
+	let mut flush_start: Option<DateTime<Utc>> = None;
+	let mut last_iteration = None;
+	let mut input_counter = None;
+	let mut input_tracking_started = false;
+	let mut last_retention: Option<DateTime<Utc>> = None;
+	let mut inbox = log_merge::Inbox::default();
+	let sleep_watcher = power_events::start();
+	let lock = LogLock::acquire(&image_dir);
+	save_offline_since_last_run(
+		storage::open(&image_dir, &conf).as_mut(),
+		(conf
+			.get_float("main.sleep_minutes")
+			.expect(CONFIG_PARSE_ERROR)
+			* 60.0) as i64,
+	);
+	drop(lock);
 	loop {
 		match config_builder.build_cloned() {
 			Ok(c) => conf = c,
 			Err(err) => warn!("Failed to refresh configuration, {}", err),
 		};
-		do_save_current(&dirs, &image_dir, &conf);
-		plotting::do_plot(&image_dir, &conf);
-		let sleep_min = conf
+		let flush_seconds = conf
 			.get_float("main.sleep_minutes")
 			.expect(CONFIG_PARSE_ERROR);
-		std::thread::sleep(Duration::from_secs((sleep_min * 60.0) as u64));
+		let flush_seconds = (flush_seconds * 60.0) as i64;
+		let sample_seconds = conf
+			.get_float("main.sample_seconds")
+			.map(|s| s as i64)
+			.unwrap_or(flush_seconds)
+			.clamp(1, flush_seconds.max(1));
+
+		if conf.get_bool("main.track_input").unwrap_or(false) && !input_tracking_started {
+			input_counter = input_activity::start(&conf);
+			input_tracking_started = true;
+		}
+
+		let now = Utc::now();
+		// The loop only sleeps for `sample_seconds`, so a bigger jump means the computer was
+		// suspended. Samples before it are saved as is, the time after it as offline.
+		let slept_since = sleep_watcher.take();
+		let jumped_since = last_iteration
+			.filter(|last: &DateTime<Utc>| {
+				(now - *last).num_seconds() > sample_seconds + sample_seconds.max(60)
+			})
+			.map(|last| last + chrono::Duration::seconds(sample_seconds));
+		let offline_since = slept_since.or(jumped_since);
+		last_iteration = Some(now);
+
+		// taken every iteration, so that events while idle aren't counted for the next sample
+		let input_events = input_counter.as_ref().and_then(|c| c.take());
+		if let Some(mut sample) = open_sample.take() {
+			let end = offline_since.unwrap_or(now);
+			sample.seconds = (end - sample.time).num_seconds().max(0) as u64;
+			sample.input_events = input_events;
+			samples.push(sample);
+		}
+		let flush_due =
+			flush_start.is_some_and(|started| (now - started).num_seconds() >= flush_seconds);
+		if flush_due || offline_since.is_some() {
+			// before opening, which may upgrade the database
+			let lock = LogLock::acquire(&image_dir);
+			let mut storage = storage::open(&image_dir, &conf);
+			do_save_current(storage.as_mut(), &conf, &samples);
+			samples.clear();
+			flush_start = None;
+			if let Some(offline_since) = offline_since {
+				info!("offline since {}", offline_since);
+				save_offline(storage.as_mut(), offline_since, now);
+			}
+			inbox.merge_changed(storage.as_mut(), &conf);
+			if last_retention.is_none_or(|last| (now - last).num_hours() >= 24) {
+				retention::apply(storage.as_mut(), &conf, dirs.config_dir());
+				last_retention = Some(now);
+			}
+			// rewrites replace the file as a whole, so plotting can read without the lock
+			drop(lock);
+			plotting::do_plot(&image_dir, &conf, storage.as_mut());
+		}
+		flush_start.get_or_insert(now);
+		open_sample = take_sample(&dirs, &conf, now);
+		std::thread::sleep(Duration::from_secs(sample_seconds as u64));
 	}
 }
@@ -6,52 +6,227 @@ extern crate fs2;
 extern crate gnuplot;
 extern crate open;
 
+use crate::log_entry::EntryKind;
+use crate::log_merge;
+use crate::storage::Storage;
+use crate::svg_plot;
 use crate::timeplot_constants::CONFIG_PARSE_ERROR;
-use crate::timeplot_constants::DATE_FORMAT;
-use crate::timeplot_constants::FILE_SEEK;
-use crate::timeplot_constants::LOG_FILE_NAME;
 use chrono::prelude::*;
 use chrono::Duration;
 use config::Config;
+use log::debug;
 use log::warn;
 use std::cmp::min;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::io::SeekFrom;
 use std::ops::Not;
 use std::ops::Sub;
 use std::path::Path;
 
-/// The part of log entry that needs to be parsed.
-struct LogEntry {
+/// Bottom of the y axis, a bit below zero so that lines at zero stay visible
+pub const Y_MIN: f64 = -0.1;
+
+/// Colors for projects, which unlike categories don't have configured ones.
+const PROJECT_COLORS: &[&str] = &[
+	"black", "orange", "red", "green", "blue", "violet", "brown", "cyan",
+];
+
+/// The part of log entry that needs to be plotted.
+struct PlotEntry {
 	epoch_seconds: u64,
+	/// Explicit duration, if the entry has one
+	duration: Option<u64>,
+	/// Category or project, depending on `graph.group_by`, and the host if they're plotted separately
+	group: String,
 	category: String,
-}
-
-fn parse_log_line(line: &str) -> LogEntry {
-	let split: Vec<&str> = line.splitn(3, ' ').collect();
-	let parse_error = format!("Failed to parse log entry {}", line);
-	let time = Utc
-		.datetime_from_str(split.first().expect(&parse_error), DATE_FORMAT)
-		.expect(&parse_error);
-	LogEntry {
-		epoch_seconds: time.timestamp_millis() as u64 / 1000,
-		category: (*split.get(1).expect(&parse_error)).to_string(),
-	}
+	/// Other machine the entry is merged from
+	host: Option<String>,
+	input_events: Option<u64>,
+	/// Start of a period without data, until the next entry
+	offline: bool,
 }
 
 struct CategoryData {
 	category_name: String,
+	host: Option<String>,
 	color: String,
 	time_impact: u64,
+	/// Seconds of `time_impact` per local day
+	daily_seconds: HashMap<NaiveDate, u64>,
 	keys: Vec<u64>,
 	values: Vec<f32>,
+	/// Input events per minute relative to `graph.intensity_scale`, smoothed the same way as values
+	intensity: Vec<f32>,
 }
 
-pub fn do_plot(image_dir: &Path, conf: &Config) {
-	use gnuplot::*;
+/// How images are drawn (`graph.renderer`).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Renderer {
+	/// Built in, no other programs needed
+	Builtin,
+	/// With the gnuplot program
+	Gnuplot,
+}
+
+fn renderer(conf: &Config) -> Renderer {
+	match conf.get_string("graph.renderer") {
+		Ok(renderer) if renderer == "builtin" => Renderer::Builtin,
+		Ok(renderer) if renderer == "gnuplot" => Renderer::Gnuplot,
+		Ok(renderer) => {
+			warn!("Unknown graph.renderer value {}, using builtin", renderer);
+			Renderer::Builtin
+		}
+		Err(_) => Renderer::Builtin,
+	}
+}
+
+/// A graph ready to be drawn, independent of the renderer.
+/// X coordinates are days relative to now, or to the start of today.
+pub struct Graph {
+	/// Image size like "1920,300", or empty for the renderer's default
+	pub size: String,
+	pub y_max: f64,
+	pub x_label: Option<String>,
+	/// Whether to mark every day on the x axis
+	pub day_ticks: bool,
+	pub lines: Vec<GraphLine>,
+	/// Labeled vertical lines, at the x coordinate
+	pub markers: Vec<(f64, String)>,
+}
+
+/// Hours per day as stacked bars, one for each local day of the plotted range.
+pub struct DailyChart {
+	pub size: String,
+	/// Label of each day, like "10-19"
+	pub days: Vec<String>,
+	/// Categories from the bottom of the bars up
+	pub series: Vec<DailySeries>,
+}
+
+pub struct DailySeries {
+	pub caption: String,
+	pub color: String,
+	/// Hours of each day
+	pub hours: Vec<f64>,
+}
+
+pub struct GraphLine {
+	/// Legend text, nothing is shown if it's empty
+	pub caption: String,
+	pub color: String,
+	pub x: Vec<f64>,
+	/// Values, where NaN breaks the line
+	pub values: Vec<f32>,
+	/// Input intensity, drawn as a dashed line
+	pub intensity: Option<Vec<f32>>,
+	/// Lower edge of the area that is filled up to `values`, for stacked graphs
+	pub base: Option<Vec<f32>>,
+}
+
+/// Seconds since the epoch, shifted by the local UTC offset at that moment.
+/// Days are always 24 hours long in this scale, so day ticks stay at local midnight across DST changes.
+fn local_seconds(epoch_seconds: i64) -> i64 {
+	let offset = Local
+		.timestamp_opt(epoch_seconds, 0)
+		.unwrap()
+		.offset()
+		.local_minus_utc();
+	epoch_seconds + offset as i64
+}
+
+fn local_date(epoch_seconds: u64) -> NaiveDate {
+	Local
+		.timestamp_opt(epoch_seconds as i64, 0)
+		.unwrap()
+		.date_naive()
+}
+
+/// First moment of a local day, in epoch seconds. That's 01:00 where DST starts at midnight.
+fn day_start(day: NaiveDate) -> u64 {
+	let midnight = day.and_hms_opt(0, 0, 0).unwrap();
+	let start = Local
+		.from_local_datetime(&midnight)
+		.earliest()
+		.or_else(|| {
+			Local
+				.from_local_datetime(&(midnight + Duration::hours(1)))
+				.earliest()
+		})
+		.unwrap_or_else(|| panic!("Unable to find the start of {}", day));
+	start.timestamp() as u64
+}
+
+/// Add `seconds` from `start` on to the days they fall into, split at local midnight.
+fn add_daily_seconds(daily_seconds: &mut HashMap<NaiveDate, u64>, start: u64, seconds: u64) {
+	let (mut start, end) = (start, start + seconds);
+	while start < end {
+		let day = local_date(start);
+		let day_end = day_start(day.succ_opt().unwrap()).clamp(start + 1, end);
+		*daily_seconds.entry(day).or_default() += day_end - start;
+		start = day_end;
+	}
+}
+
+/// Last plotted value, skipping the gaps of offline periods.
+fn last_value(values: &[f32]) -> Option<f32> {
+	values.iter().rev().find(|v| !v.is_nan()).cloned()
+}
+
+/// Lower and upper edge of a stacked category
+type Area = (Vec<f32>, Vec<f32>);
+
+/// Shares of the categories stacked on top of each other, the first one at the top.
+/// Categories start at different times and skip some offline periods, so they're first aligned
+/// on the keys of all of them. A category keeps its previous value at keys it doesn't have.
+/// Returns the keys, and the area of each category at them.
+fn stack(categories: &[CategoryData]) -> (Vec<u64>, Vec<Area>) {
+	let mut positions = vec![0; categories.len()];
+	let mut keys = Vec::new();
+	let mut columns = vec![Vec::new(); categories.len()];
+	// keys of every category go from newest to oldest
+	while let Some(key) = categories
+		.iter()
+		.zip(&positions)
+		.filter_map(|(category, position)| category.keys.get(*position))
+		.max()
+		.cloned()
+	{
+		keys.push(key);
+		for (index, category) in categories.iter().enumerate() {
+			let position = positions[index];
+			let value = if category.keys.get(position) == Some(&key) {
+				positions[index] += 1;
+				category.values[position]
+			} else if position == 0 {
+				// newer than any entry of the category
+				0.0
+			} else {
+				category.values[position - 1]
+			};
+			columns[index].push(value);
+		}
+	}
+	let mut result = vec![(Vec::new(), Vec::new()); categories.len()];
+	for point in 0..keys.len() {
+		// NaN if any category is offline, which breaks all areas
+		let total: f32 = columns.iter().map(|column| column[point]).sum();
+		let mut base = 0.0;
+		for (index, column) in columns.iter().enumerate().rev() {
+			let share = if total > 0.0 {
+				column[point] / total
+			} else if total.is_nan() {
+				f32::NAN
+			} else {
+				0.0
+			};
+			result[index].0.push(base);
+			result[index].1.push(base + share);
+			base += share;
+		}
+	}
+	(keys, result)
+}
+
+pub fn do_plot(image_dir: &Path, conf: &Config, storage: &mut dyn Storage) {
 	let sleep_seconds = conf
 		.get_float("main.sleep_minutes")
 		.expect(CONFIG_PARSE_ERROR);
@@ -76,47 +251,98 @@ pub fn do_plot(image_dir: &Path, conf: &Config) {
 		min_time
 	};
 	let min_time = min_time.timestamp() as u64;
-	let log_file = image_dir.join(LOG_FILE_NAME);
-	let log_file = File::open(&log_file)
-		.unwrap_or_else(|err| panic!("Failed to open log file {:?}, {}", log_file, err));
-	let mut log_file = BufReader::new(log_file);
-
-	// seek forward until we reach recent entries
-	let mut pos = 0;
-	loop {
-		pos += FILE_SEEK;
-		log_file
-			.seek(SeekFrom::Start(pos))
-			.unwrap_or_else(|err| panic!("{}:{} seeking failed, {}", file!(), line!(), err));
-		log_file
-			.read_until(b'\n', &mut Vec::new())
-			.unwrap_or_else(|err| panic!("failed reading till first newline {}", err));
-		log_file
-			.read_until(b'\n', &mut Vec::new())
-			.unwrap_or_else(|err| panic!("failed reading till second newline {}", err));
-		let mut line = String::new();
-		log_file
-			.read_line(&mut line)
-			.expect("Failed to read line from log (file seeking to find latest entries)");
-		if line.is_empty() || parse_log_line(&line).epoch_seconds > min_time {
-			pos -= FILE_SEEK;
-			log_file
-				.seek(SeekFrom::Start(pos))
-				.expect("Failed to seek log file (to find latest entries)");
-			if pos > 0 {
-				log_file
-					.read_until(b'\n', &mut Vec::new())
-					.unwrap_or_else(|err| panic!("{}:{}, {}", file!(), line!(), err));
-			}
-			break;
+	let contents = match storage.entries_since(min_time) {
+		Ok(contents) => contents,
+		Err(err) => {
+			warn!("Not plotting, {}", err);
+			return;
 		}
-	}
+	};
 
-	let mut lines: Vec<_> = log_file
-		.lines()
-		.map(|l| parse_log_line(&l.expect("failed to get log line")))
+	let group_by_project = match conf.get_string("graph.group_by") {
+		Ok(group_by) if group_by == "project" => true,
+		Ok(group_by) if group_by == "category" => false,
+		Ok(group_by) => {
+			warn!("Unknown graph.group_by value {}, using category", group_by);
+			false
+		}
+		Err(_) => false,
+	};
+	let stacked = match conf.get_string("graph.mode") {
+		Ok(mode) if mode == "stacked" => true,
+		Ok(mode) if mode == "lines" => false,
+		Ok(mode) => {
+			warn!("Unknown graph.mode value {}, using lines", mode);
+			false
+		}
+		Err(_) => false,
+	};
+	let separate_hosts = match conf.get_string("graph.hosts") {
+		Ok(hosts) if hosts == "separate" => true,
+		Ok(hosts) if hosts == "combined" => false,
+		Ok(hosts) => {
+			warn!("Unknown graph.hosts value {}, using combined", hosts);
+			false
+		}
+		Err(_) => false,
+	};
+	let local_host = if separate_hosts {
+		log_merge::local_hostname()
+	} else {
+		String::new()
+	};
+	for error in &contents.errors {
+		debug!("Skipping log line, {}", error);
+	}
+	let (markers, entries): (Vec<_>, Vec<_>) = contents
+		.entries
+		.into_iter()
+		.partition(|entry| entry.kind == EntryKind::Marker);
+	let markers: Vec<_> = markers
+		.into_iter()
+		.filter(|marker| marker.epoch_seconds() >= min_time)
+		.collect();
+	let mut lines: Vec<_> = entries
+		.into_iter()
+		.map(|entry| {
+			let epoch_seconds = entry.epoch_seconds();
+			let hidden = conf
+				.get_bool(&format!("category.{}.hide", &entry.category))
+				.unwrap_or(false);
+			// hidden entries still take time, but don't get their own line
+			let group = if hidden {
+				String::new()
+			} else if group_by_project {
+				entry.project.unwrap_or_else(|| "(no project)".to_string())
+			} else {
+				entry.category.clone()
+			};
+			let group = if separate_hosts && !group.is_empty() {
+				let host = entry.host.as_deref().unwrap_or(&local_host);
+				format!("{}@{}", group, host)
+			} else {
+				group
+			};
+			PlotEntry {
+				epoch_seconds,
+				duration: entry.duration,
+				group,
+				category: entry.category,
+				host: entry.host,
+				input_events: entry.input_events,
+				offline: entry.kind == EntryKind::Offline,
+			}
+		})
 		.collect();
 	lines.reverse();
+	if !contents.errors.is_empty() {
+		warn!(
+			"Skipped {} log lines that could not be parsed, see `timeplot log check`",
+			contents.errors.len()
+		);
+	}
+
+	let intensity_scale = conf.get_float("graph.intensity_scale").unwrap_or(60.0) as f32;
 
 	let mut categories: HashMap<&str, CategoryData> = HashMap::new();
 	// TODO: pre-fill categories to have deterministic order
@@ -126,21 +352,43 @@ pub fn do_plot(image_dir: &Path, conf: &Config) {
 		if line.epoch_seconds < min_time {
 			continue;
 		}
-		if !conf
-			.get_bool(&format!("category.{}.hide", &line.category))
-			.unwrap_or(false)
-			&& categories.contains_key(line.category.as_str()).not()
-		{
+		if line.offline {
+			if !separate_hosts && line.host.is_some() {
+				// another machine being offline says nothing about this one
+				continue;
+			}
+			// There's no data while offline, break the lines instead of letting them decay.
+			line.epoch_seconds = min(line.epoch_seconds, last_time);
+			let offline_host = line.host.as_ref();
+			let categories = categories
+				.values_mut()
+				.filter(|category| !separate_hosts || category.host.as_ref() == offline_host);
+			for category in categories {
+				category.keys.push(line.epoch_seconds);
+				category.values.push(f32::NAN);
+				category.intensity.push(f32::NAN);
+			}
+			last_time = line.epoch_seconds;
+			continue;
+		}
+		if !line.group.is_empty() && categories.contains_key(line.group.as_str()).not() {
 			let is_empty = categories.is_empty();
+			let color = if group_by_project {
+				PROJECT_COLORS[categories.len() % PROJECT_COLORS.len()].to_string()
+			} else {
+				conf.get_string(&format!("category.{}.color", &line.category))
+					.unwrap_or_else(|_| "black".to_string())
+			};
 			categories.insert(
-				&line.category,
+				&line.group,
 				CategoryData {
-					category_name: line.category.to_string(),
-					color: conf
-						.get_string(&format!("category.{}.color", &line.category))
-						.unwrap_or_else(|_| "black".to_string()),
+					category_name: line.group.to_string(),
+					host: line.host.clone(),
+					color,
 					time_impact: 0,
+					daily_seconds: HashMap::new(),
 					values: if is_empty { Vec::new() } else { vec![0.0] },
+					intensity: if is_empty { Vec::new() } else { vec![0.0] },
 					keys: if is_empty {
 						Vec::new()
 					} else {
@@ -150,70 +398,210 @@ pub fn do_plot(image_dir: &Path, conf: &Config) {
 			);
 		}
 		line.epoch_seconds = min(line.epoch_seconds, last_time);
-		while last_time > line.epoch_seconds + sleep_seconds {
+		// entries without explicit duration last until the next one, but no longer than an iteration
+		let covered = line
+			.duration
+			.map_or(sleep_seconds, |d| d.max(sleep_seconds));
+		while last_time > line.epoch_seconds + covered {
 			last_time -= sleep_seconds;
 			for category in categories.values_mut() {
-				let last = category.values.last().cloned();
+				let last = last_value(&category.values);
 				category.keys.push(last_time);
 				category
 					.values
 					.push(last.unwrap_or(0.0) * data_absence_modifier);
+				let last = last_value(&category.intensity);
+				category
+					.intensity
+					.push(last.unwrap_or(0.0) * data_absence_modifier);
 			}
 		}
 		let time_diff = last_time - line.epoch_seconds;
 		let weight_old = (time_diff as f32 / smoothing).exp2();
 		let weight_new = 1.0 - weight_old;
 		for category in categories.values_mut() {
-			if line.category == category.category_name {
-				category.time_impact += min(time_diff, sleep_seconds);
+			if line.group == category.category_name {
+				let seconds = min(time_diff, line.duration.unwrap_or(sleep_seconds));
+				category.time_impact += seconds;
+				add_daily_seconds(&mut category.daily_seconds, line.epoch_seconds, seconds);
 			};
-			let latest = if line.category == category.category_name {
+			let latest = if line.group == category.category_name {
 				1.0
 			} else {
 				0.0
 			};
-			let old_value = category.values.last().cloned().unwrap_or(latest);
-			let new_value = Some(latest * weight_new + old_value * weight_old);
+			let old_value = last_value(&category.values).unwrap_or(latest);
+			let new_value = latest * weight_new + old_value * weight_old;
 			category.keys.push(line.epoch_seconds);
-			category.values.push(new_value.unwrap_or(0.0));
+			category.values.push(new_value);
+
+			let latest = if line.group == category.category_name {
+				let minutes = line.duration.unwrap_or(sleep_seconds).max(1) as f32 / 60.0;
+				line.input_events.unwrap_or(0) as f32 / minutes / intensity_scale
+			} else {
+				0.0
+			};
+			let old_value = last_value(&category.intensity).unwrap_or(latest);
+			let new_value = latest * weight_new + old_value * weight_old;
+			category.intensity.push(new_value);
 		}
 		last_time = line.epoch_seconds;
 	}
 
-	let mut figure = Figure::new();
-
-	let size_override = conf.get_string("graph.size").expect(CONFIG_PARSE_ERROR);
-	let size_override = size_override.trim();
+	let size = conf.get_string("graph.size").expect(CONFIG_PARSE_ERROR);
 	let label_format = conf
 		.get_string("graph.line_format")
 		.expect(CONFIG_PARSE_ERROR);
 	let show_date = conf.get_bool("graph.show_date").expect(CONFIG_PARSE_ERROR);
+	let show_intensity = conf.get_bool("graph.show_intensity").unwrap_or(false);
+	let show_markers = conf.get_bool("graph.show_markers").unwrap_or(true);
 	let show_day_ticks = conf
 		.get_bool("graph.show_day_ticks")
 		.or_else(|_| conf.get_bool("graph.show_day_labels"))
 		.expect(CONFIG_PARSE_ERROR);
+	let day_starts_at_00 = conf.get_bool("graph.day_starts_at_00").unwrap_or(true);
+	let time_now = if day_starts_at_00 {
+		let date = Local::now().date_naive().and_hms_opt(0, 0, 0);
+		let date = date.unwrap_or_else(|| panic!("Unable to get current timestamp"));
+		date.timestamp()
+	} else {
+		local_seconds(Utc::now().timestamp())
+	};
+	// days relative to `time_now`
+	let x_coord = |epoch_seconds: u64| {
+		(local_seconds(epoch_seconds as i64) - time_now) as f64 / 60.0 / 60.0 / 24.0
+	};
+
+	let mut categories: Vec<_> = categories.into_values().collect();
+	categories.sort_unstable_by_key(|a| a.time_impact);
+	let caption = |category: &CategoryData| {
+		let minutes = (category.time_impact as f64 / 60.0).floor() as i64;
+		let hours = format!("{}:{:02}", minutes / 60, minutes % 60);
+		label_format
+			.replace("%hours%", &hours)
+			.replace("%category%", &category.category_name)
+	};
+	let daily_chart = if conf.get_bool("graph.daily_bars").unwrap_or(false) {
+		let mut days = Vec::new();
+		let mut day = local_date(min_time);
+		// a first day that's only partly plotted would look like a short one, except for today
+		if day_start(day) < min_time && day < Local::now().date_naive() {
+			day = day.succ_opt().unwrap();
+		}
+		while day <= Local::now().date_naive() {
+			days.push(day);
+			day = day.succ_opt().unwrap();
+		}
+		Some(DailyChart {
+			size: size.trim().to_string(),
+			days: days
+				.iter()
+				.map(|day| day.format("%m-%d").to_string())
+				.collect(),
+			// the longest at the bottom
+			series: categories
+				.iter()
+				.rev()
+				.map(|category| DailySeries {
+					caption: caption(category),
+					color: category.color.clone(),
+					hours: days
+						.iter()
+						.map(|day| {
+							let seconds = category.daily_seconds.get(day).cloned();
+							seconds.unwrap_or(0) as f64 / 60.0 / 60.0
+						})
+						.collect(),
+				})
+				.collect(),
+		})
+	} else {
+		None
+	};
+	let lines = if stacked {
+		let (keys, areas) = stack(&categories);
+		let x: Vec<f64> = keys.iter().map(|x| x_coord(*x)).collect();
+		categories
+			.iter()
+			.zip(areas)
+			.map(|(category, (base, top))| GraphLine {
+				caption: caption(category),
+				color: category.color.clone(),
+				x: x.clone(),
+				values: top,
+				intensity: None,
+				base: Some(base),
+			})
+			.collect()
+	} else {
+		categories
+			.into_iter()
+			.map(|category| GraphLine {
+				caption: caption(&category),
+				x: category.keys.iter().map(|x| x_coord(*x)).collect(),
+				values: category.values,
+				intensity: if show_intensity {
+					Some(category.intensity)
+				} else {
+					None
+				},
+				color: category.color,
+				base: None,
+			})
+			.collect()
+	};
+	let graph = Graph {
+		size: size.trim().to_string(),
+		// stacked shares add up to 1, any more is empty space
+		y_max: if stacked {
+			1.0
+		} else {
+			conf.get_float("graph.height_scale")
+				.expect(CONFIG_PARSE_ERROR)
+		},
+		x_label: if show_date {
+			Some(
+				Local::now()
+					.naive_local()
+					.format("created at: %Y-%m-%d %H:%M")
+					.to_string(),
+			)
+		} else {
+			None
+		},
+		day_ticks: show_day_ticks,
+		lines,
+		markers: markers
+			.iter()
+			.filter(|_| show_markers)
+			.map(|marker| (x_coord(marker.epoch_seconds()), marker.title.clone()))
+			.collect(),
+	};
+	match renderer(conf) {
+		Renderer::Builtin => svg_plot::plot(&graph, image_dir),
+		Renderer::Gnuplot => plot_with_gnuplot(&graph, image_dir),
+	}
+	if let Some(daily_chart) = daily_chart {
+		match renderer(conf) {
+			Renderer::Builtin => svg_plot::plot_daily(&daily_chart, image_dir),
+			Renderer::Gnuplot => plot_daily_with_gnuplot(&daily_chart, image_dir),
+		}
+	}
+}
 
+fn plot_with_gnuplot(graph: &Graph, image_dir: &Path) {
+	use gnuplot::*;
+	let mut figure = Figure::new();
 	{
 		let axes = figure
 			.axes2d()
 			.set_y_ticks(None, &[], &[])
 			.set_border(false, &[], &[])
-			.set_y_range(
-				Fix(-0.1),
-				Fix(conf
-					.get_float("graph.height_scale")
-					.expect(CONFIG_PARSE_ERROR)),
-			);
-		if show_date {
-			axes.set_x_label(
-				&Local::now()
-					.naive_local()
-					.format("created at: %Y-%m-%d %H:%M")
-					.to_string(),
-				&[],
-			);
+			.set_y_range(Fix(Y_MIN), Fix(graph.y_max));
+		if let Some(x_label) = &graph.x_label {
+			axes.set_x_label(x_label, &[]);
 		}
-		if show_day_ticks {
+		if graph.day_ticks {
 			axes.set_x_ticks(
 				Some((Fix(1.0), 0)),
 				&[OnAxis(false), Inward(false), Mirror(false)],
@@ -222,57 +610,141 @@ pub fn do_plot(image_dir: &Path, conf: &Config) {
 		} else {
 			axes.set_x_ticks(None, &[], &[]);
 		}
-		let mut categories: Vec<_> = categories.values().collect();
-		categories.sort_unstable_by(|a, b| a.time_impact.cmp(&b.time_impact));
-		for category in categories {
-			let minutes = (category.time_impact as f64 / 60.0).floor() as i64;
-			let hours = format!("{}:{:02}", minutes / 60, minutes % 60);
-			let caption = label_format
-				.replace("%hours%", &hours)
-				.replace("%category%", &category.category_name);
-			let day_starts_at_00 = conf.get_bool("graph.day_starts_at_00").unwrap_or(true);
-			let time_now = if day_starts_at_00 {
-				let date = Utc::now().date_naive().and_hms_opt(0, 0, 0);
-				let date = date.unwrap_or_else(|| panic!("Unable to get current timestamp"));
-				date.timestamp_millis() / 1000
-			} else {
-				Utc::now().timestamp_millis() / 1000
-			};
-			let x_coord: Vec<_> = category
-				.keys
-				.iter()
-				.map(|x| (*x as f64 - time_now as f64) / 60.0 / 60.0 / 24.0)
-				.collect();
+		for line in &graph.lines {
+			if let Some(base) = &line.base {
+				axes.fill_between(
+					&line.x,
+					base,
+					&line.values,
+					&[Caption(&line.caption), Color(&line.color)],
+				);
+				continue;
+			}
 			axes.lines(
-				&x_coord,
-				&category.values,
+				&line.x,
+				&line.values,
 				&[
-					Caption(&caption),
-					Color(&category.color),
+					Caption(&line.caption),
+					Color(&line.color),
 					PointSize(1.0),
 					PointSymbol('*'),
 				],
 			);
+			if let Some(intensity) = &line.intensity {
+				axes.lines(
+					&line.x,
+					intensity,
+					&[Caption(""), Color(&line.color), LineStyle(Dash)],
+				);
+			}
+		}
+		for (x, text) in &graph.markers {
+			axes.arrow(
+				Axis(*x),
+				Graph(0.0),
+				Axis(*x),
+				Graph(1.0),
+				&[ArrowType(NoArrow), Color("gray"), LineStyle(Dash)],
+			);
+			// read bottom to top, ending at the top of the line
+			axes.label(
+				text,
+				Axis(*x),
+				Graph(1.0),
+				&[
+					Rotate(90.0),
+					TextAlign(AlignRight),
+					TextOffset(-0.5, 0.0),
+					TextColor("gray"),
+				],
+			);
 		}
 	}
-	let size_suffix = if size_override.is_empty() {
+	show_with_gnuplot(&mut figure, &graph.size, image_dir, "image");
+}
+
+fn plot_daily_with_gnuplot(chart: &DailyChart, image_dir: &Path) {
+	use gnuplot::*;
+	let mut figure = Figure::new();
+	{
+		let axes = figure
+			.axes2d()
+			.set_border(false, &[], &[])
+			.set_y_label("hours", &[])
+			.set_x_range(Fix(-0.5), Fix(chart.days.len() as f64 - 0.5));
+		axes.set_x_ticks_custom(
+			chart
+				.days
+				.iter()
+				.enumerate()
+				.map(|(index, day)| Major(index as f64, Fix(day.clone()))),
+			&[OnAxis(false), Inward(false), Mirror(false)],
+			&[],
+		);
+		let x: Vec<f64> = (0..chart.days.len()).map(|index| index as f64).collect();
+		let widths = vec![0.8; chart.days.len()];
+		let mut tops = vec![0.0; chart.days.len()];
+		let mut stacked = Vec::new();
+		for series in &chart.series {
+			for (top, hours) in tops.iter_mut().zip(&series.hours) {
+				*top += hours;
+			}
+			stacked.push((series, tops.clone()));
+		}
+		// boxes start at zero, so the top ones are drawn first and covered by the ones below
+		for (series, tops) in stacked.iter().rev() {
+			axes.boxes_set_width(
+				&x,
+				tops,
+				&widths,
+				&[
+					Caption(&series.caption),
+					Color(&series.color),
+					FillAlpha(1.0),
+					BorderColor("white"),
+				],
+			);
+		}
+	}
+	show_with_gnuplot(&mut figure, &chart.size, image_dir, "daily");
+}
+
+/// Write `name`.svg and `name`.png
+fn show_with_gnuplot(figure: &mut gnuplot::Figure, size: &str, image_dir: &Path, name: &str) {
+	let size_suffix = if size.is_empty() {
 		"".to_string()
 	} else {
-		format!(" size {}", size_override)
+		format!(" size {}", size)
 	};
 	figure.set_terminal(
 		&format!("svg{}", size_suffix),
-		image_dir.join("image.svg").to_str().unwrap(),
+		image_dir.join(format!("{}.svg", name)).to_str().unwrap(),
 	);
 	if let Err(err) = figure.show() {
 		warn!("Failed to plot svg image, {}", err);
 	};
 	figure.set_terminal(
 		&format!("pngcairo{}", size_suffix),
-		image_dir.join("image.png").to_str().unwrap(),
+		image_dir.join(format!("{}.png", name)).to_str().unwrap(),
 	);
 	if let Err(err) = figure.show() {
 		warn!("Failed to plot png image, {}", err);
 	};
 	figure.close();
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn daily_seconds_are_split_at_midnight() {
+		let day = NaiveDate::from_ymd_opt(2018, 10, 10).unwrap();
+		let mut daily_seconds = HashMap::new();
+		add_daily_seconds(&mut daily_seconds, day_start(day) - 600, 1200);
+		add_daily_seconds(&mut daily_seconds, day_start(day) + 60, 60);
+		assert_eq!(daily_seconds.len(), 2);
+		assert_eq!(daily_seconds[&day.pred_opt().unwrap()], 600);
+		assert_eq!(daily_seconds[&day], 660);
+	}
+}
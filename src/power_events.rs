@@ -0,0 +1,77 @@
+use chrono::prelude::*;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// Remembers when the system last announced going to sleep or shutting down.
+#[derive(Default)]
+pub struct SleepWatcher {
+	sleep_started: Arc<Mutex<Option<DateTime<Utc>>>>,
+}
+
+impl SleepWatcher {
+	/// Moment the system started going offline, if it did since the previous call.
+	pub fn take(&self) -> Option<DateTime<Utc>> {
+		self.sleep_started.lock().unwrap().take()
+	}
+}
+
+/// Start listening to logind's PrepareForSleep and PrepareForShutdown signals.
+///
+/// On Linux this runs `dbus-monitor` in the background. Without it (or on other OS-es),
+/// offline periods are still detected by wall clock jumps, just less precisely.
+#[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
+pub fn start() -> SleepWatcher {
+	use log::debug;
+	use log::warn;
+	use std::io::BufRead;
+	use std::io::BufReader;
+	use std::process::Command;
+	use std::process::Stdio;
+
+	let watcher = SleepWatcher::default();
+	let child = Command::new("dbus-monitor")
+		.arg("--system")
+		.arg("type='signal',interface='org.freedesktop.login1.Manager',member='PrepareForSleep'")
+		.arg("type='signal',interface='org.freedesktop.login1.Manager',member='PrepareForShutdown'")
+		.stdout(Stdio::piped())
+		.stderr(Stdio::null())
+		.spawn();
+	let mut child = match child {
+		Ok(child) => child,
+		Err(err) => {
+			debug!(
+				"Failed to run dbus-monitor, sleep won't be detected precisely. {}",
+				err
+			);
+			return watcher;
+		}
+	};
+	let stdout = match child.stdout.take() {
+		Some(stdout) => stdout,
+		None => return watcher,
+	};
+	let sleep_started = watcher.sleep_started.clone();
+	std::thread::spawn(move || {
+		let mut in_signal = false;
+		for line in BufReader::new(stdout).lines() {
+			let line = match line {
+				Ok(line) => line,
+				Err(_) => break,
+			};
+			// The signal header is followed by its argument: "true" before sleep, "false" after
+			if line.starts_with("signal ") {
+				in_signal = line.contains("member=PrepareFor");
+			} else if in_signal && line.trim() == "boolean true" {
+				*sleep_started.lock().unwrap() = Some(Utc::now());
+				in_signal = false;
+			}
+		}
+		warn!("dbus-monitor exited, sleep is no longer detected precisely");
+		let _ = child.wait();
+	});
+	watcher
+}
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+pub fn start() -> SleepWatcher {
+	SleepWatcher::default()
+}
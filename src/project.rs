@@ -0,0 +1,125 @@
+use crate::WindowActivityInformation;
+use config::Config;
+use lazy_static::lazy_static;
+use log::warn;
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Regex table key that applies to windows of any application.
+const ANY_APPLICATION: &str = "*";
+
+lazy_static! {
+	/// Compiled `[projects.regex]` patterns, `None` for invalid ones.
+	/// Config is re-read every iteration, so they're looked up by their text.
+	static ref REGEXES: Mutex<HashMap<String, Option<Regex>>> = Mutex::new(HashMap::new());
+}
+
+fn compiled(key: &str, pattern: &str) -> Option<Regex> {
+	let mut regexes = REGEXES.lock().unwrap();
+	let regex = regexes.entry(pattern.to_string()).or_insert_with(|| {
+		Regex::new(pattern)
+			.map_err(|err| warn!("Invalid project regex for {}, {}", key, err))
+			.ok()
+	});
+	// cheap, the compiled program is shared
+	regex.clone()
+}
+
+/// Find out which project the active window belongs to.
+///
+/// First, the regexes from `[projects.regex]` are tried against the window title.
+/// Then, if `projects.git_root` is enabled, the working directory of the window's
+/// process (or of its deepest child, e.g. the shell inside a terminal) is resolved
+/// to the enclosing git repository.
+pub fn detect_project(activity_info: &WindowActivityInformation, conf: &Config) -> Option<String> {
+	let application = activity_info.pid.and_then(process_name);
+	let patterns = conf.get_table("projects.regex").unwrap_or_default();
+	// application-specific pattern takes precedence over the generic one
+	let keys = application
+		.iter()
+		.map(String::as_str)
+		.chain(Some(ANY_APPLICATION));
+	for key in keys {
+		let pattern = match patterns.get(key).map(|p| p.clone().into_string()) {
+			None => continue,
+			Some(Ok(pattern)) => pattern,
+			Some(Err(err)) => {
+				warn!("Project regex for {} is not a string, {}", key, err);
+				continue;
+			}
+		};
+		let regex = match compiled(key, &pattern) {
+			Some(regex) => regex,
+			None => continue,
+		};
+		if let Some(captures) = regex.captures(&activity_info.window_name) {
+			let project = captures.name("project").or_else(|| captures.get(1));
+			if let Some(project) = project {
+				return Some(project.as_str().trim().to_string());
+			}
+		}
+	}
+	if conf.get_bool("projects.git_root").unwrap_or(false) {
+		return activity_info
+			.pid
+			.and_then(process_cwd)
+			.and_then(|cwd| git_root_name(&cwd));
+	}
+	None
+}
+
+fn git_root_name(dir: &Path) -> Option<String> {
+	dir.ancestors()
+		.find(|dir| dir.join(".git").exists())
+		.and_then(|root| root.file_name())
+		.map(|name| name.to_string_lossy().to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn process_name(pid: u32) -> Option<String> {
+	let name = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+	Some(name.trim().to_lowercase())
+}
+#[cfg(not(target_os = "linux"))]
+fn process_name(_: u32) -> Option<String> {
+	None
+}
+
+/// Working directory of the deepest descendant of the process.
+/// For terminals this is the shell (or the program running in it),
+/// for editors it's usually the editor itself.
+#[cfg(target_os = "linux")]
+fn process_cwd(pid: u32) -> Option<std::path::PathBuf> {
+	use std::collections::HashMap;
+	let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+	for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+		let child: u32 = match entry.file_name().to_string_lossy().parse() {
+			Ok(child) => child,
+			Err(_) => continue,
+		};
+		let stat = match std::fs::read_to_string(entry.path().join("stat")) {
+			Ok(stat) => stat,
+			Err(_) => continue,
+		};
+		// the process name is in parentheses and may contain spaces, skip it
+		let parent = stat
+			.rsplit_once(')')
+			.and_then(|(_, rest)| rest.split_whitespace().nth(1))
+			.and_then(|parent| parent.parse().ok());
+		if let Some(parent) = parent {
+			children.entry(parent).or_default().push(child);
+		}
+	}
+	let mut pid = pid;
+	// the newest child is usually the one in the foreground
+	while let Some(child) = children.get(&pid).and_then(|c| c.iter().max()) {
+		pid = *child;
+	}
+	std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()
+}
+#[cfg(not(target_os = "linux"))]
+fn process_cwd(_: u32) -> Option<std::path::PathBuf> {
+	None
+}
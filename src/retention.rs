@@ -0,0 +1,101 @@
+use crate::log_crypto;
+use crate::log_entry::LogEntry;
+use crate::storage::Storage;
+use chrono::prelude::*;
+use config::Config;
+use hmac::Hmac;
+use hmac::Mac;
+use log::info;
+use log::warn;
+use sha2::Sha256;
+use std::path::Path;
+
+const HASH_PREFIX: &str = "hmac:";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TitleRetention {
+	/// Remove window titles
+	Drop,
+	/// Replace window titles with a short keyed hash, so equal titles can still be told apart.
+	/// The key is random per install and kept in the config directory, away from the log,
+	/// so common titles can't be recovered from the log by hashing guesses.
+	Hash,
+}
+
+/// `retention.titles_days` and `retention.titles`, if titles are to be removed at all.
+fn configured(conf: &Config) -> Option<(f64, TitleRetention)> {
+	let days = conf.get_float("retention.titles_days").ok()?;
+	let retention = match conf.get_string("retention.titles") {
+		Ok(titles) if titles == "hash" => TitleRetention::Hash,
+		Ok(titles) if titles == "drop" => TitleRetention::Drop,
+		Ok(titles) => {
+			warn!("Unknown retention.titles value {}, dropping titles", titles);
+			TitleRetention::Drop
+		}
+		Err(_) => TitleRetention::Drop,
+	};
+	Some((days, retention))
+}
+
+/// Short keyed hash of a title. Empty and already hashed titles are returned unchanged.
+fn hash_title(title: &str, key: &[u8]) -> String {
+	if title.is_empty() || title.starts_with(HASH_PREFIX) {
+		return title.to_string();
+	}
+	let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+	mac.update(title.as_bytes());
+	let hash: String = mac
+		.finalize()
+		.into_bytes()
+		.iter()
+		.map(|b| format!("{:02x}", b))
+		.collect();
+	format!("{}{}", HASH_PREFIX, &hash[..16])
+}
+
+/// Remove or hash the titles of an entry. Returns whether anything changed.
+fn strip_titles(entry: &mut LogEntry, retention: TitleRetention, key: &[u8]) -> bool {
+	match retention {
+		TitleRetention::Drop => {
+			let changed = !entry.title.is_empty() || !entry.titles.is_empty();
+			entry.title.clear();
+			entry.titles.clear();
+			changed
+		}
+		TitleRetention::Hash => {
+			let mut changed = false;
+			let titles = entry.titles.iter_mut().map(|t| &mut t.title);
+			for title in std::iter::once(&mut entry.title).chain(titles) {
+				let hashed = hash_title(title, key);
+				if hashed != *title {
+					*title = hashed;
+					changed = true;
+				}
+			}
+			changed
+		}
+	}
+}
+
+/// Strip window titles from entries older than `retention.titles_days`.
+/// Timestamps, durations, categories and projects are kept, and so are notes of manual entries.
+pub fn apply(storage: &mut dyn Storage, conf: &Config, config_dir: &Path) {
+	let (days, retention) = match configured(conf) {
+		Some(configured) => configured,
+		None => return,
+	};
+	let key = match retention {
+		TitleRetention::Hash => log_crypto::title_key(config_dir),
+		TitleRetention::Drop => Vec::new(),
+	};
+	let before = Utc::now().timestamp() - (days * 24.0 * 3600.0) as i64;
+	let changed = storage.rewrite_range(0, before.max(0) as u64, &mut |entry| {
+		!entry.manual && strip_titles(entry, retention, &key)
+	});
+	if changed > 0 {
+		info!(
+			"Retention: {:?} titles of {} entries older than {} days",
+			retention, changed, days
+		);
+	}
+}
@@ -0,0 +1,48 @@
+use crate::timeplot_constants::RULES_FILE_NAME;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Category rules from rules_simple.txt: a category and a window title substring per line.
+pub struct Rules {
+	/// Category and lowercase pattern, in the order of the file
+	rules: Vec<(String, String)>,
+}
+
+impl Rules {
+	pub fn load(config_dir: &Path) -> Rules {
+		let rules_file = config_dir.join(RULES_FILE_NAME);
+		let rules_file = File::open(&rules_file)
+			.unwrap_or_else(|err| panic!("Failed to open rules file {:?}, {}", rules_file, err));
+		let mut rules = Vec::new();
+		for (line_number, line) in BufReader::new(rules_file).lines().enumerate() {
+			let line = line.unwrap_or_else(|err| {
+				panic!("failed to read rules on line {}, {}", line_number, err)
+			});
+			let line = line.trim_start();
+			if line.starts_with('#') || line.is_empty() {
+				continue;
+			}
+			let split: Vec<&str> = line.splitn(2, ' ').collect();
+			let category = split[0];
+			let window_pattern = *split.get(1).unwrap_or(&"");
+			rules.push((category.to_string(), window_pattern.to_lowercase()));
+		}
+		Rules { rules }
+	}
+
+	/// Category of the first rule matching the window name.
+	pub fn category(&self, window_name: &str) -> Option<&str> {
+		let window_name = window_name.to_lowercase();
+		self.rules
+			.iter()
+			.find(|(_, pattern)| window_name.contains(pattern.as_str()))
+			.map(|(category, _)| category.as_str())
+	}
+
+	/// All categories that rules can assign.
+	pub fn categories(&self) -> impl Iterator<Item = &str> {
+		self.rules.iter().map(|(category, _)| category.as_str())
+	}
+}
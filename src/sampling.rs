@@ -0,0 +1,108 @@
+use crate::log_entry;
+use crate::log_entry::LogEntry;
+use crate::log_entry::TitleDuration;
+use chrono::prelude::*;
+
+/// Activity observed at a single point in time.
+pub struct Sample {
+	pub time: DateTime<Utc>,
+	/// How long the activity lasted, measured when the next sample is taken
+	pub seconds: u64,
+	pub window_name: String,
+	pub category: String,
+	pub project: Option<String>,
+	/// Keyboard and mouse button events while the sample lasted, if tracked
+	pub input_events: Option<u64>,
+}
+
+/// Merge the samples of one flush interval into a single log record.
+///
+/// The category with the most time wins, ties going to the one seen first.
+/// The record's title and project are those of the longest-seen window of that category.
+/// If more than one window was seen, all of them are kept with their durations.
+/// Input events are added up over all samples that have them.
+pub fn aggregate(samples: &[Sample]) -> Option<LogEntry> {
+	let mut categories: Vec<(&str, u64)> = Vec::new();
+	let mut titles: Vec<(&Sample, u64)> = Vec::new();
+	for sample in samples {
+		match categories.iter_mut().find(|(c, _)| *c == sample.category) {
+			Some((_, seconds)) => *seconds += sample.seconds,
+			None => categories.push((&sample.category, sample.seconds)),
+		}
+		match titles
+			.iter_mut()
+			.find(|(s, _)| s.window_name == sample.window_name)
+		{
+			Some((_, seconds)) => *seconds += sample.seconds,
+			None => titles.push((sample, sample.seconds)),
+		}
+	}
+	// `max_by_key` returns the last maximum, so iterate backwards to prefer the first one
+	let (category, _) = categories
+		.iter()
+		.rev()
+		.max_by_key(|(_, seconds)| *seconds)?;
+	let (main, _) = titles
+		.iter()
+		.rev()
+		.filter(|(sample, _)| sample.category == *category)
+		.max_by_key(|(_, seconds)| *seconds)?;
+	Some(LogEntry {
+		time: log_entry::local_time(samples.first()?.time.timestamp()),
+		duration: Some(samples.iter().map(|s| s.seconds).sum()),
+		category: category.to_string(),
+		project: main.project.clone(),
+		titles: if titles.len() > 1 {
+			titles
+				.iter()
+				.map(|(sample, seconds)| TitleDuration {
+					seconds: *seconds,
+					title: sample.window_name.clone(),
+				})
+				.collect()
+		} else {
+			Vec::new()
+		},
+		title: main.window_name.clone(),
+		input_events: samples
+			.iter()
+			.filter_map(|s| s.input_events)
+			.reduce(|a, b| a + b),
+		..Default::default()
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn sample(seconds: u64, window_name: &str, input_events: Option<u64>) -> Sample {
+		Sample {
+			time: Utc.timestamp_opt(1_538_406_000, 0).unwrap(),
+			seconds,
+			window_name: window_name.to_string(),
+			category: "work".to_string(),
+			project: None,
+			input_events,
+		}
+	}
+
+	#[test]
+	fn input_events_are_added_up_per_sample() {
+		let samples = [
+			sample(10, "Editor", Some(3)),
+			sample(10, "Terminal", None),
+			sample(10, "Editor", Some(4)),
+		];
+		let entry = aggregate(&samples).unwrap();
+		assert_eq!(entry.input_events, Some(7));
+		assert_eq!(entry.duration, Some(30));
+		assert_eq!(entry.title, "Editor");
+	}
+
+	#[test]
+	fn no_input_events_without_tracking() {
+		let entry = aggregate(&[sample(10, "Editor", None)]).unwrap();
+		assert_eq!(entry.input_events, None);
+	}
+}
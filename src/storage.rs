@@ -0,0 +1,518 @@
+use crate::file_operations;
+use crate::log_crypto;
+use crate::log_entry;
+use crate::log_entry::EntryKind;
+use crate::log_entry::LogEntry;
+use crate::log_file;
+use crate::log_file::LogContents;
+use crate::log_file::LogFormat;
+use crate::log_rotation;
+use crate::log_rotation::Compression;
+use crate::timeplot_constants::DATE_FORMAT;
+use crate::timeplot_constants::SQLITE_FILE_NAME;
+use config::Config;
+use log::warn;
+use rusqlite::params;
+use rusqlite::Connection;
+use rusqlite::Row;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Where log entries are kept. Chosen by `main.storage` in config.
+/// Callers that change entries hold the `LogLock` meanwhile.
+pub trait Storage {
+	fn append(&mut self, entry: &LogEntry);
+	/// The most recent entry recorded on this machine (not merged from another one), if any.
+	/// Markers are skipped, they don't tell whether the computer was on.
+	fn last_entry(&mut self) -> Option<LogEntry>;
+	/// Entries starting at `since` (epoch seconds), in time order.
+	/// Older ones may be included too, callers filter by time themselves.
+	fn entries_since(&mut self, since: u64) -> Result<LogContents, String>;
+	/// Apply `change` to the entries starting in `from..to` (epoch seconds) and store the changed ones.
+	/// `change` returns whether it modified the entry. Returns the number of changed entries.
+	fn rewrite_range(
+		&mut self,
+		from: u64,
+		to: u64,
+		change: &mut dyn FnMut(&mut LogEntry) -> bool,
+	) -> usize;
+	/// Add entries that may be older than the stored ones, keeping the log sorted by time.
+	fn insert(&mut self, entries: &[LogEntry]) -> Result<(), String>;
+}
+
+pub fn open(image_dir: &Path, conf: &Config) -> Box<dyn Storage> {
+	match conf.get_string("main.storage") {
+		Ok(storage) if storage == "sqlite" => Box::new(SqliteStorage::open(image_dir)),
+		Ok(storage) if storage == "text" => Box::new(TextStorage::new(image_dir, conf)),
+		Ok(storage) => {
+			warn!("Unknown main.storage value {}, using text", storage);
+			Box::new(TextStorage::new(image_dir, conf))
+		}
+		Err(_) => Box::new(TextStorage::new(image_dir, conf)),
+	}
+}
+
+/// The plain text log, log.log, and its monthly archives if it's rotated.
+pub struct TextStorage {
+	image_dir: PathBuf,
+	rotation: Option<Compression>,
+}
+
+impl TextStorage {
+	pub fn new(image_dir: &Path, conf: &Config) -> TextStorage {
+		TextStorage {
+			image_dir: image_dir.to_path_buf(),
+			rotation: log_rotation::configured_compression(conf),
+		}
+	}
+}
+
+impl Storage for TextStorage {
+	fn append(&mut self, entry: &LogEntry) {
+		if let Some(compression) = self.rotation {
+			log_rotation::rotate_if_new_month(&self.image_dir, entry, compression);
+		}
+		log_file::append_entry(&self.image_dir, entry);
+	}
+
+	fn last_entry(&mut self) -> Option<LogEntry> {
+		let line = file_operations::read_last_line(&log_file::log_path(&self.image_dir))?;
+		if log_entry::is_header(&line) {
+			return None;
+		}
+		let entry = log_entry::parse_log_line(&line).ok()?;
+		let is_own_record =
+			|entry: &LogEntry| entry.host.is_none() && entry.kind != EntryKind::Marker;
+		if is_own_record(&entry) {
+			return Some(entry);
+		}
+		// the log ends with merged entries or markers, look further back
+		let since = entry.epoch_seconds().saturating_sub(7 * 24 * 3600);
+		let contents = self.entries_since(since).ok()?;
+		contents.entries.into_iter().rev().find(is_own_record)
+	}
+
+	fn entries_since(&mut self, since: u64) -> Result<LogContents, String> {
+		let mut contents = LogContents::default();
+		for archive in log_rotation::archives_since(&self.image_dir, since) {
+			log_rotation::read_archive(&archive, &mut contents)
+				.map_err(|err| format!("failed to read log archive {:?}, {}", archive, err))?;
+		}
+		let file_path = log_file::log_path(&self.image_dir);
+		// nothing recorded yet
+		if file_path.exists() {
+			let reader = log_file::open_since(&file_path, since)
+				.map_err(|err| format!("failed to open log file {:?}, {}", file_path, err))?;
+			log_file::parse_lines(reader, &mut contents, false)
+				.map_err(|err| format!("failed to read log file {:?}, {}", file_path, err))?;
+		}
+		// merged entries of older months may be in log.log rather than in their month's archive
+		contents.entries.sort_by_key(|entry| entry.time);
+		Ok(contents)
+	}
+
+	fn rewrite_range(
+		&mut self,
+		from: u64,
+		to: u64,
+		change: &mut dyn FnMut(&mut LogEntry) -> bool,
+	) -> usize {
+		let mut changed = 0;
+		for archive in log_rotation::archives_since(&self.image_dir, from) {
+			let mut contents = LogContents::default();
+			if let Err(err) = log_rotation::read_archive(&archive, &mut contents) {
+				warn!("Not rewriting {:?}, failed to read it, {}", archive, err);
+				continue;
+			}
+			let count = change_range(&mut contents, from, to, change, &archive);
+			if count > 0 {
+				log_rotation::write_archive(&archive, &contents.entries);
+				changed += count;
+			}
+		}
+		let file_path = log_file::log_path(&self.image_dir);
+		if let Some(format) = log_file::detect_format(&file_path) {
+			let mut contents = log_file::read_entries(&file_path);
+			let count = change_range(&mut contents, from, to, change, &file_path);
+			if count > 0 {
+				log_file::rewrite_entries(&file_path, &contents.entries, format);
+				changed += count;
+			}
+		}
+		changed
+	}
+
+	/// Entries after the last one in log.log are appended. Older ones go to the archive
+	/// of their month if the log is rotated, or else log.log is rewritten with them.
+	fn insert(&mut self, entries: &[LogEntry]) -> Result<(), String> {
+		let file_path = log_file::log_path(&self.image_dir);
+		let last = match file_operations::read_last_line(&file_path) {
+			Some(line) if !log_entry::is_header(&line) => Some(
+				log_entry::parse_log_line(&line)
+					.map_err(|err| format!("last line of {:?} is invalid, {}", file_path, err))?,
+			),
+			_ => None,
+		};
+		let mut older = entries.to_vec();
+		older.sort_by_key(|entry| entry.time);
+		let newer = match &last {
+			Some(last) => older.split_off(older.partition_point(|entry| entry.time < last.time)),
+			None => std::mem::take(&mut older),
+		};
+		if let (Some(compression), Some(last)) = (self.rotation, &last) {
+			let month = log_rotation::month_of(last);
+			let mut months: BTreeMap<String, Vec<LogEntry>> = BTreeMap::new();
+			older.retain(|entry| {
+				let entry_month = log_rotation::month_of(entry);
+				if entry_month >= month {
+					return true;
+				}
+				months.entry(entry_month).or_default().push(entry.clone());
+				false
+			});
+			for (month, entries) in months {
+				log_rotation::insert_into_archive(&self.image_dir, &month, &entries, compression)?;
+			}
+		}
+		if !older.is_empty() {
+			self.insert_into_log(&older)?;
+		}
+		for entry in &newer {
+			self.append(entry);
+		}
+		Ok(())
+	}
+}
+
+impl TextStorage {
+	/// Add entries to log.log, rewriting it sorted by time.
+	fn insert_into_log(&self, entries: &[LogEntry]) -> Result<(), String> {
+		let file_path = log_file::log_path(&self.image_dir);
+		let (format, mut contents) = match log_file::detect_format(&file_path) {
+			Some(format) => (format, log_file::read_entries(&file_path)),
+			None => (LogFormat::Structured, LogContents::default()),
+		};
+		if !contents.errors.is_empty() {
+			return Err(format!(
+				"{:?} has {} lines that could not be parsed, see `timeplot log repair`",
+				file_path,
+				contents.errors.len()
+			));
+		}
+		contents.entries.extend(entries.iter().cloned());
+		contents.entries.sort_by_key(|entry| entry.time);
+		log_file::rewrite_entries(&file_path, &contents.entries, format);
+		Ok(())
+	}
+}
+
+/// Apply `change` to the entries starting in `from..to`, unless there are lines that couldn't be
+/// parsed (rewriting the file would lose them).
+fn change_range(
+	contents: &mut LogContents,
+	from: u64,
+	to: u64,
+	change: &mut dyn FnMut(&mut LogEntry) -> bool,
+	file_path: &Path,
+) -> usize {
+	if !contents.errors.is_empty() {
+		warn!(
+			"Not rewriting {:?}, it has {} lines that could not be parsed. See `timeplot log repair`",
+			file_path,
+			contents.errors.len()
+		);
+		return 0;
+	}
+	contents
+		.entries
+		.iter_mut()
+		.filter(|entry| (from..to).contains(&entry.epoch_seconds()))
+		.map(change)
+		.filter(|changed| *changed)
+		.count()
+}
+
+/// An SQLite database, log.sqlite, with one row per entry.
+pub struct SqliteStorage {
+	connection: Connection,
+	file_path: PathBuf,
+}
+
+/// Columns in the order `read_row` expects. The row id is number 8.
+const SELECT_ENTRIES: &str =
+	"SELECT time, duration, kind, category, project, events, titles, title, id, host, manual
+	FROM entries";
+
+/// Columns added after the first version of the table, with their definitions
+const ADDED_COLUMNS: &[(&str, &str)] =
+	&[("host", "TEXT"), ("manual", "INTEGER NOT NULL DEFAULT 0")];
+
+/// How long to wait for other connections, like an `sqlite3` session, to release the database
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl SqliteStorage {
+	pub fn open(image_dir: &Path) -> SqliteStorage {
+		if log_crypto::has_key() {
+			panic!(
+				"[encryption] only applies to the text log, remove it or use main.storage=\"text\""
+			);
+		}
+		let file_path = image_dir.join(SQLITE_FILE_NAME);
+		let connection = Connection::open(&file_path)
+			.unwrap_or_else(|err| panic!("Failed to open database {:?}, {}", file_path, err));
+		connection
+			.busy_timeout(BUSY_TIMEOUT)
+			.unwrap_or_else(|err| panic!("Failed to configure {:?}, {}", file_path, err));
+		connection
+			.execute_batch(
+				"CREATE TABLE IF NOT EXISTS entries (
+					id INTEGER PRIMARY KEY,
+					epoch INTEGER NOT NULL,
+					time TEXT NOT NULL,
+					duration INTEGER,
+					kind TEXT NOT NULL,
+					category TEXT NOT NULL,
+					project TEXT,
+					host TEXT,
+					manual INTEGER NOT NULL DEFAULT 0,
+					events INTEGER,
+					titles TEXT,
+					title TEXT NOT NULL
+				);
+				CREATE INDEX IF NOT EXISTS entries_epoch ON entries (epoch);",
+			)
+			.unwrap_or_else(|err| panic!("Failed to create tables in {:?}, {}", file_path, err));
+		// databases created by older versions
+		for (column, definition) in ADDED_COLUMNS {
+			let exists: bool = connection
+				.query_row(
+					"SELECT count(*) FROM pragma_table_info('entries') WHERE name = ?1",
+					[column],
+					|row| row.get(0),
+				)
+				.unwrap_or_else(|err| panic!("Failed to query {:?}, {}", file_path, err));
+			if !exists {
+				connection
+					.execute(
+						&format!("ALTER TABLE entries ADD COLUMN {} {}", column, definition),
+						[],
+					)
+					.unwrap_or_else(|err| panic!("Failed to upgrade {:?}, {}", file_path, err));
+			}
+		}
+		SqliteStorage {
+			connection,
+			file_path,
+		}
+	}
+
+	pub fn count(&self) -> u64 {
+		self.connection
+			.query_row("SELECT count(*) FROM entries", [], |row| row.get(0))
+			.unwrap_or_else(|err| panic!("Failed to query {:?}, {}", self.file_path, err))
+	}
+
+	/// Insert many entries in a single transaction.
+	pub fn append_all(&mut self, entries: &[LogEntry]) -> Result<(), String> {
+		let result = self.connection.transaction().and_then(|transaction| {
+			for entry in entries {
+				insert(&transaction, entry)?;
+			}
+			transaction.commit()
+		});
+		result.map_err(|err| format!("failed to write to {:?}, {}", self.file_path, err))
+	}
+
+	fn query(&self, sql: &str, since: u64) -> Result<Vec<LogEntry>, String> {
+		let result = self.connection.prepare(sql).and_then(|mut statement| {
+			let rows = statement.query_map([since as i64], read_row)?;
+			rows.collect::<rusqlite::Result<Vec<_>>>()
+		});
+		result.map_err(|err| format!("failed to query {:?}, {}", self.file_path, err))
+	}
+}
+
+fn titles_json(entry: &LogEntry) -> Option<String> {
+	if entry.titles.is_empty() {
+		None
+	} else {
+		Some(serde_json::to_string(&entry.titles).expect("Failed to serialize titles"))
+	}
+}
+
+fn update(connection: &Connection, id: i64, entry: &LogEntry) -> rusqlite::Result<usize> {
+	connection.execute(
+		"UPDATE entries SET epoch = ?1, time = ?2, duration = ?3, kind = ?4, category = ?5,
+			project = ?6, events = ?7, titles = ?8, title = ?9, host = ?10, manual = ?11
+		WHERE id = ?12",
+		params![
+			entry.epoch_seconds() as i64,
+			entry.time.format(DATE_FORMAT).to_string(),
+			entry.duration.map(|d| d as i64),
+			entry.kind.name(),
+			entry.category,
+			entry.project,
+			entry.input_events.map(|e| e as i64),
+			titles_json(entry),
+			entry.title,
+			entry.host,
+			entry.manual,
+			id,
+		],
+	)
+}
+
+fn insert(connection: &Connection, entry: &LogEntry) -> rusqlite::Result<usize> {
+	connection.execute(
+		"INSERT INTO entries
+			(epoch, time, duration, kind, category, project, events, titles, title, host, manual)
+		VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+		params![
+			entry.epoch_seconds() as i64,
+			entry.time.format(DATE_FORMAT).to_string(),
+			entry.duration.map(|d| d as i64),
+			entry.kind.name(),
+			entry.category,
+			entry.project,
+			entry.input_events.map(|e| e as i64),
+			titles_json(entry),
+			entry.title,
+			entry.host,
+			entry.manual,
+		],
+	)
+}
+
+fn read_row(row: &Row) -> rusqlite::Result<LogEntry> {
+	let conversion_error = |index, err: String| {
+		rusqlite::Error::FromSqlConversionFailure(
+			index,
+			rusqlite::types::Type::Text,
+			Box::<dyn std::error::Error + Send + Sync>::from(err),
+		)
+	};
+	let time: String = row.get(0)?;
+	let kind: String = row.get(2)?;
+	let titles: Option<String> = row.get(6)?;
+	Ok(LogEntry {
+		time: log_entry::parse_time(&time).map_err(|err| conversion_error(0, err.to_string()))?,
+		duration: row.get::<_, Option<i64>>(1)?.map(|d| d as u64),
+		kind: kind
+			.parse::<EntryKind>()
+			.map_err(|err| conversion_error(2, err))?,
+		category: row.get(3)?,
+		project: row.get(4)?,
+		input_events: row.get::<_, Option<i64>>(5)?.map(|e| e as u64),
+		titles: match titles {
+			Some(titles) => {
+				serde_json::from_str(&titles).map_err(|err| conversion_error(6, err.to_string()))?
+			}
+			None => Vec::new(),
+		},
+		title: row.get(7)?,
+		host: row.get(9)?,
+		manual: row.get(10)?,
+	})
+}
+
+impl Storage for SqliteStorage {
+	fn append(&mut self, entry: &LogEntry) {
+		insert(&self.connection, entry)
+			.unwrap_or_else(|err| panic!("Failed to write to {:?}, {}", self.file_path, err));
+	}
+
+	fn last_entry(&mut self) -> Option<LogEntry> {
+		let entries = self.query(
+			&format!(
+				"{} WHERE epoch >= ?1 AND host IS NULL AND kind != 'marker'
+					ORDER BY epoch DESC, id DESC LIMIT 1",
+				SELECT_ENTRIES
+			),
+			0,
+		);
+		entries.map_err(|err| warn!("{}", err)).ok()?.pop()
+	}
+
+	fn entries_since(&mut self, since: u64) -> Result<LogContents, String> {
+		Ok(LogContents {
+			entries: self.query(
+				&format!("{} WHERE epoch >= ?1 ORDER BY epoch, id", SELECT_ENTRIES),
+				since,
+			)?,
+			errors: Vec::new(),
+		})
+	}
+
+	fn rewrite_range(
+		&mut self,
+		from: u64,
+		to: u64,
+		change: &mut dyn FnMut(&mut LogEntry) -> bool,
+	) -> usize {
+		let to = to.min(i64::MAX as u64);
+		let result = self.connection.transaction().and_then(|transaction| {
+			let rows: Vec<(i64, LogEntry)> = {
+				let mut statement = transaction.prepare(&format!(
+					"{} WHERE epoch >= ?1 AND epoch < ?2 ORDER BY epoch, id",
+					SELECT_ENTRIES
+				))?;
+				let rows = statement.query_map([from as i64, to as i64], |row| {
+					Ok((row.get(8)?, read_row(row)?))
+				})?;
+				rows.collect::<rusqlite::Result<_>>()?
+			};
+			let mut changed = 0;
+			for (id, mut entry) in rows {
+				if change(&mut entry) {
+					update(&transaction, id, &entry)?;
+					changed += 1;
+				}
+			}
+			transaction.commit()?;
+			Ok(changed)
+		});
+		result.unwrap_or_else(|err| panic!("Failed to rewrite {:?}, {}", self.file_path, err))
+	}
+
+	fn insert(&mut self, entries: &[LogEntry]) -> Result<(), String> {
+		self.append_all(entries)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::fs;
+
+	#[test]
+	fn insert_appends_newer_and_archives_older_months() {
+		let image_dir =
+			std::env::temp_dir().join(format!("timeplot-{}-insert", std::process::id()));
+		fs::create_dir_all(&image_dir).unwrap();
+		let entry = |line: &str| log_entry::parse_log_line(line).unwrap();
+		let mut storage = TextStorage {
+			image_dir: image_dir.clone(),
+			rotation: Some(Compression::None),
+		};
+		storage.append(&entry("2018-09-30T10:00:00+02:00 work Editor"));
+		storage.append(&entry("2018-10-02T10:00:00+02:00 work Editor"));
+		storage
+			.insert(&[
+				entry("2018-10-03T10:00:00+02:00 work Newer"),
+				entry("2018-09-29T10:00:00+02:00 work September"),
+				entry("2018-10-01T10:00:00+02:00 work October"),
+			])
+			.unwrap();
+		let log = log_file::read_entries(&log_file::log_path(&image_dir));
+		let mut archive = LogContents::default();
+		log_rotation::read_archive(&image_dir.join("log.2018-09.log"), &mut archive).unwrap();
+		fs::remove_dir_all(&image_dir).unwrap();
+		let titles = |contents: &LogContents| -> Vec<String> {
+			contents.entries.iter().map(|e| e.title.clone()).collect()
+		};
+		assert_eq!(titles(&log), vec!["October", "Editor", "Newer"]);
+		assert_eq!(titles(&archive), vec!["September", "Editor"]);
+	}
+}
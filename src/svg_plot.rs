@@ -0,0 +1,729 @@
+use crate::plotting::DailyChart;
+use crate::plotting::Graph;
+use crate::plotting::Y_MIN;
+use lazy_static::lazy_static;
+use log::warn;
+use resvg::tiny_skia;
+use resvg::usvg;
+use resvg::usvg::fontdb;
+use resvg::usvg::TreeParsing;
+use resvg::usvg::TreeTextToPath;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Image size if `graph.size` is empty, the same as gnuplot's
+const DEFAULT_SIZE: (u32, u32) = (640, 480);
+const FONT_SIZE: f64 = 12.0;
+const MARGIN: f64 = 10.0;
+const TICK_LENGTH: f64 = 5.0;
+const LEGEND_ROW: f64 = 16.0;
+/// Length of the line sample next to a legend caption
+const LEGEND_SAMPLE: f64 = 40.0;
+const MARKER_COLOR: &str = "gray";
+/// Approximate width of a character, to leave room for text
+const CHAR_WIDTH: f64 = FONT_SIZE * 0.6;
+/// Part of a day's width covered by its bar
+const BAR_WIDTH: f64 = 0.8;
+
+lazy_static! {
+	/// Fonts for the text of PNG images, looked up once
+	static ref FONTS: fontdb::Database = {
+		let mut fonts = fontdb::Database::new();
+		fonts.load_system_fonts();
+		// the default sans-serif family is Arial, which few Linux systems have
+		let families = ["DejaVu Sans", "Liberation Sans", "Noto Sans"];
+		let available = |family: &str| {
+			fonts
+				.faces()
+				.any(|face| face.families.iter().any(|(name, _)| name == family))
+		};
+		if let Some(family) = families.iter().find(|family| available(family)) {
+			fonts.set_sans_serif_family(*family);
+		}
+		fonts
+	};
+}
+
+/// Width and height from `graph.size` like "1920,300".
+fn parse_size(size: &str) -> (u32, u32) {
+	if size.is_empty() {
+		return DEFAULT_SIZE;
+	}
+	let parsed = size
+		.split_once(',')
+		.and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)));
+	match parsed {
+		Some((width, height)) if width > 0 && height > 0 => (width, height),
+		_ => {
+			warn!(
+				"Unknown graph.size value {}, using {},{}",
+				size, DEFAULT_SIZE.0, DEFAULT_SIZE.1
+			);
+			DEFAULT_SIZE
+		}
+	}
+}
+
+/// Color names gnuplot knows (`show colornames`), which differ from SVG's:
+/// gnuplot's "green" is SVG's "lime", and names like "dark-yellow" don't exist in SVG.
+const GNUPLOT_COLORS: &[(&str, &str)] = &[
+	("white", "#ffffff"),
+	("black", "#000000"),
+	("dark-grey", "#a0a0a0"),
+	("red", "#ff0000"),
+	("web-green", "#00c000"),
+	("web-blue", "#0080ff"),
+	("dark-magenta", "#c000ff"),
+	("dark-cyan", "#00eeee"),
+	("dark-orange", "#c04000"),
+	("dark-yellow", "#c8c800"),
+	("royalblue", "#4169e1"),
+	("goldenrod", "#ffc020"),
+	("dark-spring-green", "#008040"),
+	("purple", "#c080ff"),
+	("steelblue", "#306080"),
+	("dark-red", "#8b0000"),
+	("dark-chartreuse", "#408000"),
+	("orchid", "#ff80ff"),
+	("aquamarine", "#7fffd4"),
+	("brown", "#a52a2a"),
+	("yellow", "#ffff00"),
+	("turquoise", "#40e0d0"),
+	("grey0", "#000000"),
+	("grey10", "#1a1a1a"),
+	("grey20", "#333333"),
+	("grey30", "#4d4d4d"),
+	("grey40", "#666666"),
+	("grey50", "#7f7f7f"),
+	("grey60", "#999999"),
+	("grey70", "#b3b3b3"),
+	("grey", "#c0c0c0"),
+	("grey80", "#cccccc"),
+	("grey90", "#e5e5e5"),
+	("grey100", "#ffffff"),
+	("light-red", "#f03232"),
+	("light-green", "#90ee90"),
+	("light-blue", "#add8e6"),
+	("light-magenta", "#f055f0"),
+	("light-cyan", "#e0ffff"),
+	("light-goldenrod", "#eedd82"),
+	("light-pink", "#ffb6c1"),
+	("light-turquoise", "#afeeee"),
+	("gold", "#ffd700"),
+	("green", "#00ff00"),
+	("dark-green", "#006400"),
+	("spring-green", "#00ff7f"),
+	("forest-green", "#228b22"),
+	("sea-green", "#2e8b57"),
+	("blue", "#0000ff"),
+	("dark-blue", "#00008b"),
+	("midnight-blue", "#191970"),
+	("navy", "#000080"),
+	("medium-blue", "#0000cd"),
+	("skyblue", "#87ceeb"),
+	("cyan", "#00ffff"),
+	("magenta", "#ff00ff"),
+	("dark-turquoise", "#00ced1"),
+	("dark-pink", "#ff1493"),
+	("coral", "#ff7f50"),
+	("light-coral", "#f08080"),
+	("orange-red", "#ff4500"),
+	("salmon", "#fa8072"),
+	("dark-salmon", "#e9967a"),
+	("khaki", "#f0e68c"),
+	("dark-khaki", "#bdb76b"),
+	("dark-goldenrod", "#b8860b"),
+	("beige", "#f5f5dc"),
+	("olive", "#a08020"),
+	("orange", "#ffa500"),
+	("violet", "#ee82ee"),
+	("dark-violet", "#9400d3"),
+	("plum", "#dda0dd"),
+	("dark-plum", "#905040"),
+	("dark-olivegreen", "#556b2f"),
+	("orangered4", "#801400"),
+	("brown4", "#801414"),
+	("sienna4", "#804014"),
+	("orchid4", "#804080"),
+	("mediumpurple3", "#8060c0"),
+	("slateblue1", "#8060ff"),
+	("yellow4", "#808000"),
+	("sienna1", "#ff8040"),
+	("tan1", "#ffa040"),
+	("sandybrown", "#ffa060"),
+	("light-salmon", "#ffa070"),
+	("pink", "#ffc0c0"),
+	("khaki1", "#ffff80"),
+	("lemonchiffon", "#ffffc0"),
+	("bisque", "#cdb79e"),
+	("honeydew", "#f0fff0"),
+	("slategrey", "#a0b6cd"),
+	("seagreen", "#c1ffc1"),
+	("antiquewhite", "#cdc0b0"),
+	("chartreuse", "#7cff40"),
+	("greenyellow", "#a0ff20"),
+	("gray", "#bebebe"),
+	("light-gray", "#d3d3d3"),
+	("light-grey", "#d3d3d3"),
+	("dark-gray", "#a0a0a0"),
+	("slategray", "#a0b6cd"),
+	("gray0", "#000000"),
+	("gray10", "#1a1a1a"),
+	("gray20", "#333333"),
+	("gray30", "#4d4d4d"),
+	("gray40", "#666666"),
+	("gray50", "#7f7f7f"),
+	("gray60", "#999999"),
+	("gray70", "#b3b3b3"),
+	("gray80", "#cccccc"),
+	("gray90", "#e5e5e5"),
+	("gray100", "#ffffff"),
+];
+
+/// SVG color and opacity of a color as gnuplot understands it:
+/// "#AARRGGBB" where AA is transparency, or names like "dark-green".
+fn svg_color(color: &str) -> (String, f64) {
+	let color = color.trim();
+	if color.len() == 9 && color.starts_with('#') {
+		if let Ok(transparency) = u8::from_str_radix(&color[1..3], 16) {
+			return (
+				format!("#{}", &color[3..]),
+				1.0 - transparency as f64 / 255.0,
+			);
+		}
+	}
+	let named = GNUPLOT_COLORS
+		.iter()
+		.find(|(name, _)| name.eq_ignore_ascii_case(color));
+	match named {
+		Some((_, rgb)) => (rgb.to_string(), 1.0),
+		// "#RRGGBB", or a name gnuplot doesn't know, which SVG may still know
+		None => (color.to_string(), 1.0),
+	}
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}
+
+/// Range of x coordinates to show: all points and markers,
+/// extended to whole days if they're marked, as gnuplot does.
+fn x_range(graph: &Graph) -> (f64, f64) {
+	let xs = graph.lines.iter().flat_map(|line| line.x.iter().copied());
+	let xs = xs.chain(graph.markers.iter().map(|(x, _)| *x));
+	let (mut min, mut max) = xs.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), x| {
+		(min.min(x), max.max(x))
+	});
+	if !min.is_finite() {
+		(min, max) = (-1.0, 0.0);
+	}
+	if graph.day_ticks {
+		(min, max) = (min.floor(), max.ceil());
+	}
+	if max - min < 1e-9 {
+		(min, max) = (min - 0.5, max + 0.5);
+	}
+	(min, max)
+}
+
+/// Draw a line through the points as SVG paths, broken where values are NaN.
+fn write_line(
+	svg: &mut String,
+	points: impl Iterator<Item = (f64, f64)>,
+	color: &str,
+	dash: Option<&str>,
+) {
+	let (color, opacity) = svg_color(color);
+	let mut path = String::new();
+	// the last point drawn, while the line isn't broken
+	let mut last_point: Option<String> = None;
+	for (x, y) in points {
+		if y.is_nan() {
+			last_point = None;
+			continue;
+		}
+		// there are many more points than pixels, so skip the ones that don't move the pen
+		let point = format!("{:.1},{:.1}", x, y);
+		if last_point.as_ref() == Some(&point) {
+			continue;
+		}
+		let command = if last_point.is_some() { 'L' } else { 'M' };
+		write!(path, "{}{} ", command, point).unwrap();
+		last_point = Some(point);
+	}
+	if path.is_empty() {
+		return;
+	}
+	let dash = dash.map_or(String::new(), |d| format!(r#" stroke-dasharray="{}""#, d));
+	writeln!(
+		svg,
+		r#"<path d="{}" fill="none" stroke="{}" stroke-opacity="{}" stroke-width="1.5"{}/>"#,
+		path.trim_end(),
+		escape(&color),
+		opacity,
+		dash
+	)
+	.unwrap();
+}
+
+/// Fill the area between two lines of points with the same x coordinates,
+/// broken where either value is NaN.
+fn write_area(svg: &mut String, upper: &[(f64, f64)], lower: &[(f64, f64)], color: &str) {
+	let (color, opacity) = svg_color(color);
+	let mut path = String::new();
+	let mut run_start = 0;
+	for end in 0..=upper.len() {
+		let is_gap = |index: usize| upper[index].1.is_nan() || lower[index].1.is_nan();
+		if end < upper.len() && !is_gap(end) {
+			continue;
+		}
+		if end > run_start + 1 {
+			// along the upper edge, and back along the lower one
+			let outline = upper[run_start..end]
+				.iter()
+				.chain(lower[run_start..end].iter().rev());
+			let mut last_point = None;
+			for (x, y) in outline {
+				let point = format!("{:.1},{:.1}", x, y);
+				if last_point.as_ref() == Some(&point) {
+					continue;
+				}
+				let command = if last_point.is_some() { 'L' } else { 'M' };
+				write!(path, "{}{} ", command, point).unwrap();
+				last_point = Some(point);
+			}
+			path.push_str("Z ");
+		}
+		run_start = end + 1;
+	}
+	if path.is_empty() {
+		return;
+	}
+	writeln!(
+		svg,
+		r#"<path d="{}" fill="{}" fill-opacity="{}" stroke="none"/>"#,
+		path.trim_end(),
+		escape(&color),
+		opacity
+	)
+	.unwrap();
+}
+
+/// Draw the graph as an SVG image: category lines, legend, day ticks and markers.
+/// The output only depends on `graph`, so it can be compared with earlier results.
+pub fn render_svg(graph: &Graph) -> String {
+	let (width, height) = parse_size(&graph.size);
+	let (width, height) = (width as f64, height as f64);
+	let (x_min, x_max) = x_range(graph);
+	let (left, right, top) = (MARGIN, width - MARGIN, MARGIN);
+	let mut bottom = height - MARGIN;
+	if graph.day_ticks {
+		bottom -= TICK_LENGTH + FONT_SIZE + 2.0;
+	}
+	if graph.x_label.is_some() {
+		bottom -= FONT_SIZE + 4.0;
+	}
+	let x_pixel = |x: f64| left + (x - x_min) / (x_max - x_min) * (right - left);
+	let y_pixel = |y: f64| bottom - (y - Y_MIN) / (graph.y_max - Y_MIN) * (bottom - top);
+
+	let mut svg = String::new();
+	writeln!(
+		svg,
+		r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="{f}">"#,
+		w = width,
+		h = height,
+		f = FONT_SIZE
+	)
+	.unwrap();
+	svg.push_str("<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
+	writeln!(
+		svg,
+		r#"<clipPath id="plot"><rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}"/></clipPath>"#,
+		left,
+		top,
+		right - left,
+		bottom - top
+	)
+	.unwrap();
+
+	if graph.day_ticks {
+		for day in (x_min as i64)..=(x_max as i64) {
+			let x = x_pixel(day as f64);
+			writeln!(
+				svg,
+				r#"<line x1="{x:.1}" y1="{y:.1}" x2="{x:.1}" y2="{y2:.1}" stroke="black"/>"#,
+				x = x,
+				y = bottom,
+				y2 = bottom + TICK_LENGTH
+			)
+			.unwrap();
+			writeln!(
+				svg,
+				r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">{}</text>"#,
+				x,
+				bottom + TICK_LENGTH + FONT_SIZE,
+				day
+			)
+			.unwrap();
+		}
+	}
+	if let Some(x_label) = &graph.x_label {
+		writeln!(
+			svg,
+			r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">{}</text>"#,
+			(left + right) / 2.0,
+			height - MARGIN,
+			escape(x_label)
+		)
+		.unwrap();
+	}
+
+	svg.push_str("<g clip-path=\"url(#plot)\">\n");
+	for line in &graph.lines {
+		let points = |values: &[f32]| {
+			let values = values.iter().map(|v| y_pixel(*v as f64));
+			line.x
+				.iter()
+				.map(|x| x_pixel(*x))
+				.zip(values)
+				.collect::<Vec<_>>()
+		};
+		if let Some(base) = &line.base {
+			write_area(&mut svg, &points(&line.values), &points(base), &line.color);
+			continue;
+		}
+		write_line(
+			&mut svg,
+			points(&line.values).into_iter(),
+			&line.color,
+			None,
+		);
+		if let Some(intensity) = &line.intensity {
+			write_line(
+				&mut svg,
+				points(intensity).into_iter(),
+				&line.color,
+				Some("6,4"),
+			);
+		}
+	}
+	svg.push_str("</g>\n");
+
+	for (x, text) in &graph.markers {
+		let x = x_pixel(*x);
+		writeln!(
+			svg,
+			r#"<line x1="{x:.1}" y1="{top:.1}" x2="{x:.1}" y2="{bottom:.1}" stroke="{c}" stroke-dasharray="6,4"/>"#,
+			x = x,
+			top = top,
+			bottom = bottom,
+			c = MARKER_COLOR
+		)
+		.unwrap();
+		// read bottom to top, ending at the top of the line
+		writeln!(
+			svg,
+			r#"<text transform="translate({:.1},{:.1}) rotate(-90)" text-anchor="end" fill="{}">{}</text>"#,
+			x - 4.0,
+			top,
+			MARKER_COLOR,
+			escape(text)
+		)
+		.unwrap();
+	}
+
+	let captioned = graph.lines.iter().filter(|line| !line.caption.is_empty());
+	for (row, line) in captioned.enumerate() {
+		let y = top + LEGEND_ROW * (row as f64 + 0.5);
+		writeln!(
+			svg,
+			r#"<text x="{:.1}" y="{:.1}" text-anchor="end">{}</text>"#,
+			right - LEGEND_SAMPLE - 8.0,
+			y + FONT_SIZE * 0.35,
+			escape(&line.caption)
+		)
+		.unwrap();
+		if line.base.is_some() {
+			let (color, opacity) = svg_color(&line.color);
+			writeln!(
+				svg,
+				r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}" fill-opacity="{}"/>"#,
+				right - LEGEND_SAMPLE,
+				y - LEGEND_ROW / 4.0,
+				LEGEND_SAMPLE,
+				LEGEND_ROW / 2.0,
+				escape(&color),
+				opacity
+			)
+			.unwrap();
+			continue;
+		}
+		let sample = [(right - LEGEND_SAMPLE, y), (right, y)];
+		write_line(&mut svg, sample.iter().copied(), &line.color, None);
+	}
+	svg.push_str("</svg>\n");
+	svg
+}
+
+/// Replace the file through a temporary one, so that image viewers never see half of it.
+fn write_replacing(file_path: &Path, content: &[u8]) -> Result<(), String> {
+	let temp_path = file_path.with_extension("tmp");
+	fs::write(&temp_path, content)
+		.and_then(|_| fs::rename(&temp_path, file_path))
+		.map_err(|err| err.to_string())
+}
+
+fn write_png(svg: &str, file_path: &Path) -> Result<(), String> {
+	let mut tree =
+		usvg::Tree::from_str(svg, &usvg::Options::default()).map_err(|err| err.to_string())?;
+	tree.convert_text(&FONTS);
+	let tree = resvg::Tree::from_usvg(&tree);
+	let size = tree.size.to_int_size();
+	let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+		.ok_or_else(|| "the image is empty".to_string())?;
+	tree.render(tiny_skia::Transform::default(), &mut pixmap.as_mut());
+	let png = pixmap.encode_png().map_err(|err| err.to_string())?;
+	write_replacing(file_path, &png)
+}
+
+/// Draw the chart as an SVG image: bars with an hour scale on the left and the legend on the right.
+pub fn render_daily_svg(chart: &DailyChart) -> String {
+	let (width, height) = parse_size(&chart.size);
+	let (width, height) = (width as f64, height as f64);
+	let captions = chart.series.iter().filter(|s| !s.caption.is_empty());
+	let longest_caption = captions.map(|s| s.caption.chars().count()).max();
+	let legend_width = longest_caption.map_or(0.0, |chars| {
+		chars as f64 * CHAR_WIDTH + LEGEND_SAMPLE + 2.0 * MARGIN
+	});
+	let (left, top) = (MARGIN + 4.0 * CHAR_WIDTH, MARGIN);
+	let right = (width - MARGIN - legend_width).max(left + 1.0);
+	let bottom = height - MARGIN - TICK_LENGTH - FONT_SIZE;
+	let day_count = chart.days.len().max(1) as f64;
+	let mut totals = vec![0.0; chart.days.len()];
+	for series in &chart.series {
+		for (total, hours) in totals.iter_mut().zip(&series.hours) {
+			*total += hours;
+		}
+	}
+	let max_hours = totals.iter().cloned().fold(1.0, f64::max).ceil();
+	// at most about 8 hour lines
+	let hour_step = (max_hours / 8.0).ceil();
+	let day_width = (right - left) / day_count;
+	let x_pixel = |day: f64| left + day * day_width;
+	let y_pixel = |hours: f64| bottom - hours / max_hours * (bottom - top);
+
+	let mut svg = String::new();
+	writeln!(
+		svg,
+		r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="{f}">"#,
+		w = width,
+		h = height,
+		f = FONT_SIZE
+	)
+	.unwrap();
+	svg.push_str("<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
+	let mut hours = 0.0;
+	while hours <= max_hours {
+		let y = y_pixel(hours);
+		writeln!(
+			svg,
+			r#"<line x1="{:.1}" y1="{y:.1}" x2="{:.1}" y2="{y:.1}" stroke="lightgray"/>"#,
+			left,
+			right,
+			y = y
+		)
+		.unwrap();
+		writeln!(
+			svg,
+			r#"<text x="{:.1}" y="{:.1}" text-anchor="end">{}h</text>"#,
+			left - 4.0,
+			y + FONT_SIZE * 0.35,
+			hours
+		)
+		.unwrap();
+		hours += hour_step;
+	}
+	// label every day if there's room for it, or every few days otherwise
+	let longest_day = chart.days.iter().map(|d| d.chars().count()).max();
+	let label_width = longest_day.unwrap_or(0) as f64 * CHAR_WIDTH + 4.0;
+	let label_every = (label_width / day_width).ceil().max(1.0) as usize;
+	for (index, day) in chart.days.iter().enumerate().step_by(label_every) {
+		writeln!(
+			svg,
+			r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">{}</text>"#,
+			x_pixel(index as f64 + 0.5),
+			bottom + TICK_LENGTH + FONT_SIZE,
+			escape(day)
+		)
+		.unwrap();
+	}
+
+	let mut bases = vec![0.0; chart.days.len()];
+	for series in &chart.series {
+		let (color, opacity) = svg_color(&series.color);
+		for (index, (base, hours)) in bases.iter_mut().zip(&series.hours).enumerate() {
+			if *hours > 0.0 {
+				writeln!(
+					svg,
+					r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}" fill-opacity="{}"/>"#,
+					x_pixel(index as f64 + (1.0 - BAR_WIDTH) / 2.0),
+					y_pixel(*base + hours),
+					day_width * BAR_WIDTH,
+					y_pixel(*base) - y_pixel(*base + hours),
+					escape(&color),
+					opacity
+				)
+				.unwrap();
+			}
+			*base += hours;
+		}
+	}
+
+	// the top of the bars first, as they're stacked
+	let captioned = chart.series.iter().rev().filter(|s| !s.caption.is_empty());
+	for (row, series) in captioned.enumerate() {
+		let y = top + LEGEND_ROW * (row as f64 + 0.5);
+		let (color, opacity) = svg_color(&series.color);
+		writeln!(
+			svg,
+			r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}" fill-opacity="{}"/>"#,
+			right + MARGIN,
+			y - LEGEND_ROW / 4.0,
+			LEGEND_SAMPLE,
+			LEGEND_ROW / 2.0,
+			escape(&color),
+			opacity
+		)
+		.unwrap();
+		writeln!(
+			svg,
+			r#"<text x="{:.1}" y="{:.1}">{}</text>"#,
+			right + MARGIN + LEGEND_SAMPLE + 8.0,
+			y + FONT_SIZE * 0.35,
+			escape(&series.caption)
+		)
+		.unwrap();
+	}
+	svg.push_str("</svg>\n");
+	svg
+}
+
+/// Write `name`.svg and `name`.png
+fn write_images(svg: &str, image_dir: &Path, name: &str) {
+	let svg_path = image_dir.join(format!("{}.svg", name));
+	if let Err(err) = write_replacing(&svg_path, svg.as_bytes()) {
+		warn!("Failed to write svg image, {}", err);
+	}
+	if let Err(err) = write_png(svg, &image_dir.join(format!("{}.png", name))) {
+		warn!("Failed to write png image, {}", err);
+	}
+}
+
+/// Write image.svg and image.png without any external program.
+pub fn plot(graph: &Graph, image_dir: &Path) {
+	write_images(&render_svg(graph), image_dir, "image");
+}
+
+/// Write daily.svg and daily.png without any external program.
+pub fn plot_daily(chart: &DailyChart, image_dir: &Path) {
+	write_images(&render_daily_svg(chart), image_dir, "daily");
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::plotting::DailySeries;
+	use crate::plotting::GraphLine;
+
+	/// Compare with the expected SVG in src/snapshots.
+	/// Run the tests with UPDATE_SNAPSHOTS=1 to accept a changed rendering.
+	fn assert_snapshot(name: &str, svg: &str) {
+		let file_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+			.join("src/snapshots")
+			.join(name);
+		if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+			fs::write(&file_path, svg).unwrap();
+			return;
+		}
+		let expected = fs::read_to_string(&file_path)
+			.unwrap_or_else(|err| panic!("Failed to read {:?}, {}", file_path, err));
+		assert!(
+			svg == expected,
+			"{} differs from {:?}:\n{}",
+			name,
+			file_path,
+			svg
+		);
+	}
+
+	#[test]
+	fn graph_snapshot() {
+		let x: Vec<f64> = (0..9).map(|i| 17800.0 + i as f64 / 4.0).collect();
+		let graph = Graph {
+			size: "400,200".to_string(),
+			y_max: 1.2,
+			x_label: Some("days <local>".to_string()),
+			day_ticks: true,
+			lines: vec![
+				GraphLine {
+					caption: "work".to_string(),
+					color: "dark-green".to_string(),
+					x: x.clone(),
+					values: vec![0.0, 0.5, 1.0, f32::NAN, 0.8, 0.8, 0.2, 0.0, 0.1],
+					intensity: Some(vec![0.0, 0.2, 0.4, 0.4, 0.3, 0.3, 0.1, 0.0, 0.0]),
+					base: None,
+				},
+				GraphLine {
+					caption: "personal".to_string(),
+					color: "#7FFF00CC".to_string(),
+					x,
+					values: vec![0.2, 0.3, 0.3, 0.2, 0.1, 0.1, 0.4, 0.6, 0.6],
+					intensity: None,
+					base: Some(vec![0.0; 9]),
+				},
+			],
+			markers: vec![(17801.0, "started & stopped".to_string())],
+		};
+		assert_snapshot("graph.svg", &render_svg(&graph));
+	}
+
+	#[test]
+	fn daily_snapshot() {
+		let chart = DailyChart {
+			size: "300,200".to_string(),
+			days: vec![
+				"10-01".to_string(),
+				"10-02".to_string(),
+				"10-03".to_string(),
+			],
+			series: vec![
+				DailySeries {
+					caption: "work".to_string(),
+					color: "dark-yellow".to_string(),
+					hours: vec![6.5, 0.0, 8.0],
+				},
+				DailySeries {
+					caption: "personal".to_string(),
+					color: "light-red".to_string(),
+					hours: vec![2.0, 3.5, 1.0],
+				},
+			],
+		};
+		assert_snapshot("daily.svg", &render_daily_svg(&chart));
+	}
+
+	#[test]
+	fn gnuplot_color_names() {
+		assert_eq!(svg_color("dark-yellow"), ("#c8c800".to_string(), 1.0));
+		assert_eq!(svg_color("green"), ("#00ff00".to_string(), 1.0));
+		assert_eq!(svg_color("#FF00CC"), ("#FF00CC".to_string(), 1.0));
+		assert_eq!(
+			svg_color("rebeccapurple"),
+			("rebeccapurple".to_string(), 1.0)
+		);
+	}
+}
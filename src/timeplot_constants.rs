@@ -1,8 +1,25 @@
-pub const FILE_SEEK: u64 = 100_000;
-
 pub const CONFIG_PARSE_ERROR: &str =
 	"Failed to parse config file. Consider removing/renaming it so it'll be recreated.";
 
+/// Activity is not logged after this long without keyboard or mouse input
+pub const MAX_IDLE_SECONDS: u32 = 180;
+
 pub const LOG_FILE_NAME: &str = "log.log";
 
-pub const DATE_FORMAT: &str = "%Y-%m-%d_%H:%M";
+pub const SQLITE_FILE_NAME: &str = "log.sqlite";
+
+pub const SALT_FILE_NAME: &str = "log.salt";
+
+/// Secret key for hashing old window titles, see `retention.titles`
+pub const TITLE_KEY_FILE_NAME: &str = "title.key";
+
+/// Held while the log is being changed, by the daemon and by commands like `timeplot amend`
+pub const LOCK_FILE_NAME: &str = "log.lock";
+
+pub const RULES_FILE_NAME: &str = "rules_simple.txt";
+
+/// Local time with seconds and UTC offset, e.g. `2018-10-01T17:03:27+02:00`
+pub const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%:z";
+
+/// UTC time in minutes, as written by older versions
+pub const LEGACY_DATE_FORMAT: &str = "%Y-%m-%d_%H:%M";